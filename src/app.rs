@@ -10,7 +10,9 @@ use tui::backend::TermionBackend;
 use tui::layout::{Constraint, Direction, Layout};
 use tui::Terminal;
 
+use crate::dir_sizes::DirSize;
 use crate::file_manager::FileManager;
+use crate::git::LoadedStatus;
 use crate::shell;
 use crate::status_bar::{Mode, StatusBar};
 use crate::system_monitor::SystemMonitor;
@@ -31,6 +33,8 @@ pub struct App {
     keys: channel::Receiver<Key>,
     ticks: Receiver<Instant>,
     watch_events: Receiver<notify::Event>,
+    dir_sizes: Receiver<DirSize>,
+    git_statuses: Receiver<LoadedStatus>,
     task_events: Receiver<task_manager::Event>,
     shell_events: Receiver<shell::Event>,
 }
@@ -38,7 +42,7 @@ pub struct App {
 impl App {
     pub fn new() -> Result<App> {
         let system_monitor = SystemMonitor::new();
-        let (file_manager, watch_events) = FileManager::new()?;
+        let (file_manager, watch_events, dir_sizes, git_statuses) = FileManager::new()?;
         let (task_manager, task_events) = TaskManager::new()?;
         let status_bar = StatusBar::new();
 
@@ -63,6 +67,8 @@ impl App {
             keys,
             ticks: channel::tick(Duration::from_secs(2)),
             watch_events,
+            dir_sizes,
+            git_statuses,
             task_events,
             shell_events,
         })
@@ -100,12 +106,12 @@ impl App {
                     self.task_manager.draw(&mut frame, chunks[2]);
                 }
                 self.status_bar
-                    .draw(&mut self.file_manager, &mut frame, chunks[3]);
+                    .draw(&self.file_manager, &mut frame, chunks[3]);
             })?;
 
             let bottom = terminal.size()?.bottom() - 1;
             match &self.status_bar.mode {
-                Mode::Ask { prompt, .. } => {
+                Mode::Ask { prompt, .. } | Mode::Choose { prompt, .. } => {
                     terminal.set_cursor(prompt.len() as u16, bottom)?;
                     terminal.show_cursor()?;
                 }
@@ -128,7 +134,7 @@ impl App {
                 recv(self.keys) -> key => {
                     let key = key.unwrap();
                     match self.status_bar.mode {
-                        Mode::Ask { .. } | Mode::Edit { .. } => {
+                        Mode::Ask { .. } | Mode::Choose { .. } | Mode::Select { .. } | Mode::Edit { .. } => {
                             catch_error!(self.status_bar.on_key(key, &mut self.file_manager, &mut self.task_manager));
                         }
                         _ => match key {
@@ -143,7 +149,7 @@ impl App {
                                 InputFocus::TaskManager => self.input_focus = InputFocus::FileManager,
                             }
                             key => catch_error!(match self.input_focus {
-                                InputFocus::FileManager => self.file_manager.on_key(key, &mut self.status_bar),
+                                InputFocus::FileManager => self.file_manager.on_key(key, &mut self.status_bar, &mut self.task_manager),
                                 InputFocus::TaskManager => self.task_manager.on_key(key, &mut self.status_bar),
                             })
                         }
@@ -155,12 +161,14 @@ impl App {
                     self.status_bar.on_tick(tick);
                 }
                 recv(self.watch_events) -> watch => catch_error!(self.file_manager.on_notify(watch.unwrap())),
+                recv(self.dir_sizes) -> size => self.file_manager.on_dir_size(size.unwrap()),
+                recv(self.git_statuses) -> status => self.file_manager.on_git_status(status.unwrap()),
                 recv(self.task_events) -> task_event => self.task_manager.on_event(task_event.unwrap()),
                 recv(self.shell_events) -> shell_event => {
                     catch_error!(match shell_event.unwrap() {
                         shell::Event::Exit => break,
                         shell::Event::Task { command, rendered } => self.task_manager.new_task(command, rendered),
-                        event => self.file_manager.on_shell_event(event),
+                        event => self.file_manager.on_shell_event(event, &mut self.status_bar),
                     })
                 }
             }
@@ -0,0 +1,635 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::{symlink, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nix::libc;
+use nix::sys::stat::SFlag;
+use tar::EntryType;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::dir_sizes::total_size;
+use crate::file_manager::{FileInfo, Metadata, Symlink};
+use crate::task_manager::TaskManager;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        let format = if name.ends_with(".tar") {
+            Format::Tar
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Format::TarGz
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Format::TarZst
+        } else if name.ends_with(".zip") {
+            Format::Zip
+        } else {
+            return None;
+        };
+        Some(format)
+    }
+}
+
+/// Whether `path` looks like an archive that can be browsed.
+pub fn is_archive(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Split a path going through an archive into the archive and the path inside it.
+pub fn split(path: &Path) -> Option<(&Path, &Path)> {
+    path.ancestors()
+        .find(|p| is_archive(p) && p.is_file())
+        .map(|archive| (archive, path.strip_prefix(archive).unwrap()))
+}
+
+/// Drop `.`, `..` and the root, so member paths can't point outside the archive.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+fn open_tar(path: &Path, format: Format) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match format {
+        Format::TarGz => Box::new(GzDecoder::new(file)),
+        Format::TarZst => Box::new(zstd::Decoder::with_buffer(file)?),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+struct Member {
+    metadata: Metadata,
+    link: Option<PathBuf>,
+}
+
+/// The listing of an archive, browsed like a read-only directory tree.
+pub struct Archive {
+    pub path: PathBuf,
+    /// Keyed by the path inside the archive. Directories missing from
+    /// the archive are filled in, and their sizes include their contents.
+    members: BTreeMap<PathBuf, Member>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> io::Result<Archive> {
+        let mut members = BTreeMap::new();
+        match Format::of(path) {
+            Some(Format::Zip) => {
+                let mut zip = ZipArchive::new(File::open(path)?)?;
+                for i in 0..zip.len() {
+                    let file = zip.by_index_raw(i)?;
+                    let name = match file.enclosed_name() {
+                        Some(name) => normalize(name),
+                        None => continue,
+                    };
+                    let kind = if file.is_dir() {
+                        SFlag::S_IFDIR
+                    } else {
+                        SFlag::S_IFREG
+                    };
+                    let mode = match file.unix_mode() {
+                        Some(mode) if mode & SFlag::S_IFMT.bits() != 0 => mode,
+                        Some(mode) => mode | kind.bits(),
+                        None if file.is_dir() => kind.bits() | 0o755,
+                        None => kind.bits() | 0o644,
+                    };
+                    let metadata = Metadata::new(mode, file.size());
+                    drop(file);
+                    let mut link = None;
+                    if mode & SFlag::S_IFMT.bits() == SFlag::S_IFLNK.bits() {
+                        let mut target = String::new();
+                        zip.by_index(i)?.read_to_string(&mut target)?;
+                        link = Some(PathBuf::from(target));
+                    }
+                    members.insert(name, Member { metadata, link });
+                }
+            }
+            Some(format) => {
+                let mut tar = open_tar(path, format)?;
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    let header = entry.header();
+                    let kind = match header.entry_type() {
+                        EntryType::Directory => SFlag::S_IFDIR,
+                        EntryType::Symlink => SFlag::S_IFLNK,
+                        EntryType::Char => SFlag::S_IFCHR,
+                        EntryType::Block => SFlag::S_IFBLK,
+                        EntryType::Fifo => SFlag::S_IFIFO,
+                        EntryType::Regular | EntryType::Link | EntryType::Continuous => {
+                            SFlag::S_IFREG
+                        }
+                        _ => continue,
+                    };
+                    let mode = header.mode()? & 0o7777 | kind.bits();
+                    let member = Member {
+                        metadata: Metadata::new(mode, header.size()?),
+                        link: match kind {
+                            SFlag::S_IFLNK => entry.link_name()?.map(|l| l.into_owned()),
+                            _ => None,
+                        },
+                    };
+                    members.insert(normalize(&entry.path()?), member);
+                }
+            }
+            None => return Err(io::Error::other("Not an archive")),
+        }
+        members.remove(Path::new(""));
+
+        let files: Vec<(PathBuf, u64)> = members
+            .iter()
+            .filter(|(_, m)| !m.metadata.is_dir())
+            .map(|(path, m)| (path.clone(), m.metadata.len()))
+            .collect();
+        for (path, len) in files {
+            for dir in path.ancestors().skip(1).filter(|d| d != &Path::new("")) {
+                let dir = members.entry(dir.to_owned()).or_insert_with(|| Member {
+                    metadata: Metadata::new(SFlag::S_IFDIR.bits() | 0o755, 0),
+                    link: None,
+                });
+                dir.metadata = Metadata::new(dir.metadata.mode(), dir.metadata.len() + len);
+            }
+        }
+
+        Ok(Archive {
+            path: path.to_owned(),
+            members,
+        })
+    }
+
+    /// Size of a member, including the contents of directories.
+    pub fn size(&self, member: &Path) -> u64 {
+        self.members.get(member).map_or(0, |m| m.metadata.len())
+    }
+
+    /// The members at the root of the archive.
+    pub fn top_level(&self) -> Vec<PathBuf> {
+        self.members
+            .keys()
+            .filter(|path| path.parent() == Some(Path::new("")))
+            .cloned()
+            .collect()
+    }
+
+    /// List the members in `dir`, a path going through the archive.
+    pub fn read_dir(&self, dir: &Path) -> io::Result<Vec<FileInfo>> {
+        let inner = dir.strip_prefix(&self.path).unwrap_or(dir);
+        if inner != Path::new("") && !self.members.get(inner).is_some_and(|m| m.metadata.is_dir()) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No such directory in the archive",
+            ));
+        }
+        Ok(self
+            .members
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(inner))
+            .map(|(path, member)| {
+                let name = path.file_name().unwrap_or_default().to_owned();
+                FileInfo {
+                    path: dir.join(&name),
+                    extension: path.extension().map(OsStr::to_owned),
+                    name,
+                    metadata: member.metadata.clone(),
+                    symlink: member.link.clone().map(|target| Symlink {
+                        target,
+                        broken: false,
+                    }),
+                }
+            })
+            .collect())
+    }
+}
+
+/// The files in `dest` that extracting `members` would overwrite.
+pub fn conflicts(members: &[PathBuf], dest: &Path) -> Vec<PathBuf> {
+    members
+        .iter()
+        .map(|m| dest.join(m.file_name().unwrap_or_default()))
+        .filter(|target| fs::symlink_metadata(target).is_ok())
+        .collect()
+}
+
+/// Remove a file about to be overwritten. Directories are merged instead.
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// Create the directories from `dest` down to `dir`, refusing to go through symlinks
+/// so that links planted by earlier members can't lead outside of `dest`.
+fn create_dirs_in(dest: &Path, dir: &Path) -> Result<()> {
+    let mut path = dest.to_owned();
+    for component in dir.strip_prefix(dest)?.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                bail!("Refusing to extract through the symlink {}", path.display())
+            }
+            Ok(_) => bail!("{} is not a directory", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&path)?,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` is a file extracted into `dest`, reached without going
+/// through a symlink that could lead outside of it.
+fn is_file_in(dest: &Path, path: &Path) -> bool {
+    let parent = match path.parent().map(Path::canonicalize) {
+        Some(Ok(parent)) => parent,
+        _ => return false,
+    };
+    let in_dest = dest
+        .canonicalize()
+        .is_ok_and(|dest| parent.starts_with(dest));
+    in_dest && fs::symlink_metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/// Extract `members` of `archive` into `dest`, each keeping its own name
+/// and directories bringing their contents. Existing files are only replaced
+/// if `overwrite` is set. `on_member` is called with the path and size of each
+/// member before it is written, and can stop the extraction by returning an error.
+pub fn extract(
+    archive: &Path,
+    members: &[PathBuf],
+    dest: &Path,
+    overwrite: bool,
+    mut on_member: impl FnMut(&Path, u64) -> Result<()>,
+) -> Result<()> {
+    let members: Vec<PathBuf> = members.iter().map(|m| normalize(m)).collect();
+    if let Some(target) = conflicts(&members, dest).first().filter(|_| !overwrite) {
+        bail!("{} already exists", target.display());
+    }
+    // Where a member path is extracted to, if it was chosen.
+    let target_of = |path: &Path| {
+        let member = members.iter().find(|m| path.starts_with(m))?;
+        Some(dest.join(path.strip_prefix(member.parent()?).ok()?))
+    };
+
+    match Format::of(archive) {
+        Some(Format::Zip) => {
+            let mut zip = ZipArchive::new(File::open(archive)?)?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                let path = match file.enclosed_name() {
+                    Some(name) => normalize(name),
+                    None => continue,
+                };
+                let target = match target_of(&path) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                on_member(&path, file.size())?;
+                if file.is_dir() {
+                    create_dirs_in(dest, &target)?;
+                    continue;
+                }
+                if let Some(parent) = target.parent() {
+                    create_dirs_in(dest, parent)?;
+                }
+                if overwrite {
+                    remove_existing(&target)?;
+                }
+                let mode = file.unix_mode();
+                if mode.is_some_and(|m| m & SFlag::S_IFMT.bits() == SFlag::S_IFLNK.bits()) {
+                    let mut link = String::new();
+                    file.read_to_string(&mut link)?;
+                    symlink(link, &target)?;
+                } else {
+                    let mut writer = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .custom_flags(libc::O_NOFOLLOW)
+                        .open(&target)?;
+                    io::copy(&mut file, &mut writer)?;
+                    if let Some(mode) = mode {
+                        fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o7777))?;
+                    }
+                }
+            }
+        }
+        Some(format) => {
+            let mut tar = open_tar(archive, format)?;
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let path = normalize(&entry.path()?);
+                let target = match target_of(&path) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                on_member(&path, entry.header().size()?)?;
+                if let Some(parent) = target.parent() {
+                    create_dirs_in(dest, parent)?;
+                }
+                if overwrite {
+                    remove_existing(&target)?;
+                }
+                if entry.header().entry_type() == EntryType::Link {
+                    // Hard links point at a member extracted earlier, if it was chosen.
+                    let linked = entry.link_name()?.and_then(|l| target_of(&normalize(&l)));
+                    if let Some(linked) = linked.filter(|l| is_file_in(dest, l)) {
+                        fs::hard_link(linked, &target)?;
+                    }
+                    continue;
+                }
+                // Members keeping their path in the archive are checked by `tar` too.
+                if target == dest.join(&path) {
+                    entry.unpack_in(dest)?;
+                } else {
+                    entry.unpack(&target)?;
+                }
+            }
+        }
+        None => bail!("Not an archive"),
+    }
+    Ok(())
+}
+
+/// Extract `members` of `archive` as a task, reporting progress against their `total` size.
+pub fn spawn_extract(
+    task_manager: &mut TaskManager,
+    archive: PathBuf,
+    members: Vec<PathBuf>,
+    total: u64,
+    dest: PathBuf,
+    overwrite: bool,
+) {
+    let name = format!(
+        "Extract {} to {}",
+        archive.file_name().unwrap_or_default().to_string_lossy(),
+        dest.display()
+    );
+    task_manager.spawn(name, move |progress| {
+        let mut done = 0;
+        extract(&archive, &members, &dest, overwrite, |_, size| {
+            progress.check()?;
+            progress.update(format!("{}%", done * 100 / total.max(1)));
+            done += size;
+            Ok(())
+        })
+    });
+}
+
+/// Collect the files under `path`, named relative to `base` in the archive.
+fn walk(path: &Path, base: &Path, files: &mut Vec<(PathBuf, PathBuf, fs::Metadata)>) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let is_dir = metadata.is_dir();
+    let name = path.strip_prefix(base).unwrap_or(path).to_owned();
+    files.push((path.to_owned(), name, metadata));
+    if is_dir {
+        for entry in fs::read_dir(path)? {
+            walk(&entry?.path(), base, files)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(
+    writer: W,
+    files: &[(PathBuf, PathBuf, fs::Metadata)],
+    on_file: &mut impl FnMut(&Path, u64) -> Result<()>,
+) -> Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    for (path, name, metadata) in files {
+        let size = if metadata.is_dir() { 0 } else { metadata.len() };
+        on_file(path, size)?;
+        builder.append_path_with_name(path, name)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+fn write_zip(
+    file: File,
+    files: &[(PathBuf, PathBuf, fs::Metadata)],
+    on_file: &mut impl FnMut(&Path, u64) -> Result<()>,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(file);
+    for (path, name, metadata) in files {
+        let size = if metadata.is_dir() { 0 } else { metadata.len() };
+        on_file(path, size)?;
+        let name = name.to_string_lossy();
+        let options = FileOptions::default()
+            .unix_permissions(metadata.mode() & 0o7777)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        if metadata.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if metadata.file_type().is_symlink() {
+            let target = fs::read_link(path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Pack `files` and everything under them into `archive`, named relative
+/// to their directories. The format is chosen by the extension of `archive`.
+/// `on_file` is called like in [`extract`].
+pub fn pack(
+    archive: &Path,
+    files: &[PathBuf],
+    mut on_file: impl FnMut(&Path, u64) -> Result<()>,
+) -> Result<()> {
+    let format = match Format::of(archive) {
+        Some(format) => format,
+        None => bail!("Unsupported archive format, use .tar.gz, .tar.zst or .zip"),
+    };
+    let mut entries = vec![];
+    for file in files {
+        walk(file, file.parent().unwrap_or(file), &mut entries)?;
+    }
+    // Don't pack the archive into itself.
+    entries.retain(|(path, _, _)| path != archive);
+
+    let file = File::create(archive)?;
+    let on_file = &mut on_file;
+    let res = match format {
+        Format::Tar => {
+            write_tar(BufWriter::new(file), &entries, on_file).and_then(|mut w| Ok(w.flush()?))
+        }
+        Format::TarGz => write_tar(
+            GzEncoder::new(file, Compression::default()),
+            &entries,
+            on_file,
+        )
+        .and_then(|w| Ok(w.finish().map(drop)?)),
+        Format::TarZst => write_tar(zstd::Encoder::new(file, 0)?, &entries, on_file)
+            .and_then(|w| Ok(w.finish().map(drop)?)),
+        Format::Zip => write_zip(file, &entries, on_file),
+    };
+    if res.is_err() {
+        let _ = fs::remove_file(archive);
+    }
+    res
+}
+
+/// Pack `files` into `archive` as a task, reporting progress against their total size.
+pub fn spawn_pack(task_manager: &mut TaskManager, archive: PathBuf, files: Vec<PathBuf>) {
+    let name = format!(
+        "Pack {} file(s) into {}",
+        files.len(),
+        archive.file_name().unwrap_or_default().to_string_lossy()
+    );
+    task_manager.spawn(name, move |progress| {
+        let total: u64 = files.iter().map(|f| total_size(f)).sum();
+        let mut done = 0;
+        pack(&archive, &files, |_, size| {
+            progress.check()?;
+            progress.update(format!("{}%", done * 100 / total.max(1)));
+            done += size;
+            Ok(())
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Add an entry to a tar archive without the checks `tar` makes on its path.
+    fn append(
+        builder: &mut tar::Builder<File>,
+        path: &str,
+        kind: EntryType,
+        link: Option<&Path>,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn extract_all(archive: &Path, members: &[&str], dest: &Path) -> Result<()> {
+        let members: Vec<PathBuf> = members.iter().map(PathBuf::from).collect();
+        extract(archive, &members, dest, false, |_, _| Ok(()))
+    }
+
+    #[test]
+    fn normalize_drops_parents_and_root() {
+        assert_eq!(normalize(Path::new("../a/./b")), Path::new("a/b"));
+        assert_eq!(normalize(Path::new("/etc/passwd")), Path::new("etc/passwd"));
+        assert_eq!(normalize(Path::new("a/../../b")), Path::new("a/b"));
+    }
+
+    #[test]
+    fn create_dirs_in_refuses_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (dest, outside) = (dir.path().join("dest"), dir.path().join("outside"));
+        fs::create_dir(&dest).unwrap();
+        fs::create_dir(&outside).unwrap();
+        symlink(&outside, dest.join("d")).unwrap();
+
+        assert!(create_dirs_in(&dest, &dest.join("a/b")).is_ok());
+        assert!(dest.join("a/b").is_dir());
+        assert!(create_dirs_in(&dest, &dest.join("d/e")).is_err());
+        assert!(!outside.join("e").exists());
+    }
+
+    #[test]
+    fn extract_tar_with_parent_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, dest) = (dir.path().join("a.tar"), dir.path().join("dest"));
+        fs::create_dir(&dest).unwrap();
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        append(&mut builder, "../evil", EntryType::Regular, None, b"evil");
+        builder.finish().unwrap();
+
+        // `tar` skips the member rather than write it outside of `dest`.
+        extract_all(&archive, &["evil"], &dest).unwrap();
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn extract_tar_through_planted_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, dest) = (dir.path().join("a.tar"), dir.path().join("dest"));
+        let outside = dir.path().join("outside");
+        fs::create_dir(&dest).unwrap();
+        fs::create_dir(&outside).unwrap();
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        append(&mut builder, "d", EntryType::Symlink, Some(&outside), b"");
+        append(&mut builder, "d/evil", EntryType::Regular, None, b"evil");
+        builder.finish().unwrap();
+
+        assert!(extract_all(&archive, &["d"], &dest).is_err());
+        assert!(!outside.join("evil").exists());
+    }
+
+    #[test]
+    fn extract_tar_hard_link_through_planted_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, dest) = (dir.path().join("a.tar"), dir.path().join("dest"));
+        let outside = dir.path().join("outside");
+        fs::create_dir(&dest).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        append(&mut builder, "d", EntryType::Symlink, Some(&outside), b"");
+        append(
+            &mut builder,
+            "h",
+            EntryType::Link,
+            Some(Path::new("d/secret")),
+            b"",
+        );
+        builder.finish().unwrap();
+
+        extract_all(&archive, &["d", "h"], &dest).unwrap();
+        assert!(fs::symlink_metadata(dest.join("h")).is_err());
+        assert_eq!(fs::metadata(outside.join("secret")).unwrap().nlink(), 1);
+    }
+
+    #[test]
+    fn extract_zip_through_planted_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (archive, dest) = (dir.path().join("a.zip"), dir.path().join("dest"));
+        let outside = dir.path().join("outside");
+        fs::create_dir(&dest).unwrap();
+        fs::create_dir(&outside).unwrap();
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        let options = FileOptions::default();
+        zip.add_symlink("d", outside.to_string_lossy(), options)
+            .unwrap();
+        zip.start_file("d/evil", options).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+
+        assert!(extract_all(&archive, &["d"], &dest).is_err());
+        assert!(!outside.join("evil").exists());
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::config;
+
+const FILE: &str = "bookmarks.yml";
+
+/// Named directories, stored in `~/.config/scd/bookmarks.yml`.
+pub struct Bookmarks(pub BTreeMap<String, PathBuf>);
+
+impl Bookmarks {
+    pub fn load() -> Result<Bookmarks> {
+        Ok(Bookmarks(config::load(FILE)?))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = config::dir()?;
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(FILE), serde_yaml::to_vec(&self.0)?)?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, name: String, dir: PathBuf) -> Result<()> {
+        if name.is_empty() {
+            bail!("Bookmark name can't be empty");
+        }
+        self.0.insert(name, dir);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if self.0.remove(name).is_none() {
+            bail!("No bookmark named {}", name);
+        }
+        self.save()
+    }
+
+    /// The bookmark named `key`, or the first one starting with it.
+    pub fn find(&self, key: char) -> Option<&PathBuf> {
+        let mut buf = [0; 4];
+        let key = key.encode_utf8(&mut buf);
+        self.0.get(&*key).or_else(|| {
+            self.0
+                .iter()
+                .find(|(name, _)| name.starts_with(&*key))
+                .map(|(_, dir)| dir)
+        })
+    }
+}
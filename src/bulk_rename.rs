@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::journal::{Change, Journal, Step};
+
+/// Rename many files by editing their names in `$EDITOR`.
+///
+/// Each line of the buffer holds one path, relative to `dir` if it is inside.
+pub struct BulkRename {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    pub buffer: PathBuf,
+}
+
+impl BulkRename {
+    pub fn new(dir: PathBuf, files: Vec<PathBuf>) -> Result<BulkRename> {
+        // Names are written as raw bytes, so any name without a newline can be edited.
+        let mut text = vec![];
+        for file in &files {
+            let line = file
+                .strip_prefix(&dir)
+                .unwrap_or(file)
+                .as_os_str()
+                .as_bytes();
+            if line.contains(&b'\n') {
+                bail!("Can't edit the name of {}", file.display());
+            }
+            text.extend_from_slice(line);
+            text.push(b'\n');
+        }
+        let mut file = tempfile::Builder::new().prefix("scd-rename-").tempfile()?;
+        file.write_all(&text)?;
+        let (_, buffer) = file.keep()?;
+        Ok(BulkRename { dir, files, buffer })
+    }
+
+    /// Read the edited buffer and return the changed names as `(old, new)`.
+    pub fn read(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let text = fs::read(&self.buffer)?;
+        let text = text.strip_suffix(b"\n").unwrap_or(&text);
+        let lines: Vec<&[u8]> = match text {
+            [] => vec![],
+            text => text.split(|&b| b == b'\n').collect(),
+        };
+        ensure!(
+            lines.len() == self.files.len(),
+            "Expected {} lines but got {}",
+            self.files.len(),
+            lines.len()
+        );
+
+        let mut renames = vec![];
+        let mut targets = HashSet::new();
+        for (old, line) in self.files.iter().zip(lines) {
+            ensure!(!line.is_empty(), "Empty name for {}", old.display());
+            let new = self.dir.join(OsStr::from_bytes(line));
+            ensure!(
+                targets.insert(new.clone()),
+                "More than one file renamed to {}",
+                String::from_utf8_lossy(line)
+            );
+            if &new != old {
+                renames.push((old.clone(), new));
+            }
+        }
+
+        // Overwriting is only fine if the existing file is moved away too.
+        for (_, new) in &renames {
+            if fs::symlink_metadata(new).is_ok() && !self.files.contains(new) {
+                bail!("{} already exists", new.display());
+            }
+        }
+        Ok(renames)
+    }
+}
+
+impl Drop for BulkRename {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.buffer);
+    }
+}
+
+/// Order the renames so no file is overwritten, breaking swap cycles
+/// like `a -> b, b -> a` with a temporary name.
+pub fn plan(mut renames: Vec<(PathBuf, PathBuf)>) -> Vec<(PathBuf, PathBuf)> {
+    let mut steps = vec![];
+    let mut temp_count = 0;
+    while !renames.is_empty() {
+        let free = renames
+            .iter()
+            .position(|(_, new)| !renames.iter().any(|(old, _)| old == new));
+        match free {
+            Some(index) => steps.push(renames.remove(index)),
+            None => {
+                // Everything left is in cycles, move one file out of the way.
+                let (old, _) = &mut renames[0];
+                let temp = temp_name(old, &mut temp_count);
+                steps.push((old.clone(), temp.clone()));
+                *old = temp;
+            }
+        }
+    }
+    steps
+}
+
+fn temp_name(path: &Path, count: &mut usize) -> PathBuf {
+    loop {
+        *count += 1;
+        let temp = path.with_file_name(format!(".scd-rename-{}-{}", process::id(), count));
+        if fs::symlink_metadata(&temp).is_err() {
+            return temp;
+        }
+    }
+}
+
+/// Rename the files, recording the renames done in `journal` even if one fails.
+pub fn apply(steps: &[(PathBuf, PathBuf)], journal: &Journal) -> Result<()> {
+    let mut done = vec![];
+    let mut res = Ok(());
+    for (old, new) in steps {
+        res = fs::rename(old, new)
+            .with_context(|| format!("Failed to rename {} to {}", old.display(), new.display()));
+        if res.is_err() {
+            break;
+        }
+        done.push(Step::new(Change::Move {
+            from: old.clone(),
+            to: new.clone(),
+        }));
+    }
+    journal.record("Bulk rename", done);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Run `steps` on a map of names to contents, failing if one overwrites a file.
+    fn run(files: &[&str], steps: &[(PathBuf, PathBuf)]) -> HashMap<PathBuf, String> {
+        let mut state: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|f| (PathBuf::from(f), f.to_string()))
+            .collect();
+        for (old, new) in steps {
+            assert!(!state.contains_key(new), "{} overwritten", new.display());
+            let content = state.remove(old).expect("renamed a missing file");
+            state.insert(new.clone(), content);
+        }
+        state
+    }
+
+    fn renames(pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|(old, new)| (PathBuf::from(old), PathBuf::from(new)))
+            .collect()
+    }
+
+    #[test]
+    fn plan_chain() {
+        let steps = plan(renames(&[("/x/a", "/x/b"), ("/x/b", "/x/c")]));
+        let state = run(&["/x/a", "/x/b"], &steps);
+        assert_eq!(state[Path::new("/x/b")], "/x/a");
+        assert_eq!(state[Path::new("/x/c")], "/x/b");
+        assert_eq!(steps.len(), 2);
+    }
+
+    #[test]
+    fn plan_swap() {
+        let steps = plan(renames(&[("/x/a", "/x/b"), ("/x/b", "/x/a")]));
+        let state = run(&["/x/a", "/x/b"], &steps);
+        assert_eq!(state.len(), 2);
+        assert_eq!(state[Path::new("/x/a")], "/x/b");
+        assert_eq!(state[Path::new("/x/b")], "/x/a");
+    }
+
+    #[test]
+    fn plan_three_cycle() {
+        let steps = plan(renames(&[
+            ("/x/a", "/x/b"),
+            ("/x/b", "/x/c"),
+            ("/x/c", "/x/a"),
+        ]));
+        let state = run(&["/x/a", "/x/b", "/x/c"], &steps);
+        assert_eq!(state.len(), 3);
+        assert_eq!(state[Path::new("/x/b")], "/x/a");
+        assert_eq!(state[Path::new("/x/c")], "/x/b");
+        assert_eq!(state[Path::new("/x/a")], "/x/c");
+    }
+
+    fn edit(names: &[&str], text: &str) -> (tempfile::TempDir, Result<Vec<(PathBuf, PathBuf)>>) {
+        let dir = tempfile::tempdir().unwrap();
+        let files = names.iter().map(|n| dir.path().join(n)).collect();
+        for file in &files {
+            fs::write(file, "").unwrap();
+        }
+        let bulk_rename = BulkRename::new(dir.path().to_owned(), files).unwrap();
+        fs::write(&bulk_rename.buffer, text).unwrap();
+        let res = bulk_rename.read();
+        (dir, res)
+    }
+
+    #[test]
+    fn read_renames() {
+        let (dir, res) = edit(&["a", "b"], "a\nc\n");
+        let dir = dir.path();
+        assert_eq!(res.unwrap(), vec![(dir.join("b"), dir.join("c"))]);
+    }
+
+    #[test]
+    fn read_duplicate_targets() {
+        let (_dir, res) = edit(&["a", "b"], "c\nc\n");
+        assert!(res.unwrap_err().to_string().contains("More than one file"));
+    }
+
+    #[test]
+    fn read_non_utf8_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join(OsStr::from_bytes(b"a\xff"));
+        fs::write(&old, "").unwrap();
+        let bulk_rename = BulkRename::new(dir.path().to_owned(), vec![old.clone()]).unwrap();
+        assert_eq!(fs::read(&bulk_rename.buffer).unwrap(), b"a\xff\n");
+        fs::write(&bulk_rename.buffer, b"b\xfe").unwrap();
+        let new = dir.path().join(OsStr::from_bytes(b"b\xfe"));
+        assert_eq!(bulk_rename.read().unwrap(), vec![(old, new)]);
+    }
+
+    #[test]
+    fn read_line_count() {
+        let (_dir, res) = edit(&["a", "b"], "a\n");
+        assert!(res.unwrap_err().to_string().contains("Expected 2 lines"));
+        let (_dir, res) = edit(&["a"], "");
+        assert!(res.unwrap_err().to_string().contains("Expected 1 lines"));
+    }
+
+    #[test]
+    fn read_existing_target() {
+        let (_dir, res) = edit(&["a", "b"], "b\nc\n");
+        assert!(res.is_ok(), "b is moved away too");
+        let (_dir, res) = edit(&["a", "b"], "a\nb\n");
+        assert_eq!(res.unwrap(), vec![]);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b"), "").unwrap();
+        let bulk_rename =
+            BulkRename::new(dir.path().to_owned(), vec![dir.path().join("a")]).unwrap();
+        fs::write(&bulk_rename.buffer, "b\n").unwrap();
+        assert!(bulk_rename
+            .read()
+            .unwrap_err()
+            .to_string()
+            .contains("already exists"));
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
+/// The directory holding all config files, `~/.config/scd`.
+pub fn dir() -> Result<PathBuf> {
+    let home = env::var("HOME")?;
+    Ok(Path::new(&home).join(".config/scd"))
+}
+
+/// Load a YAML config file, or return the default if it does not exist.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let path = dir()?.join(name);
+    match fs::read_to_string(&path) {
+        Ok(buf) => Ok(serde_yaml::from_str(&buf)
+            .with_context(|| format!("Failed to parse {}", path.display()))?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Expand keys like `rs, py, go` into one entry per key.
+pub fn split_keys(raw: HashMap<String, String>) -> HashMap<String, String> {
+    let mut res = HashMap::new();
+    for (keys, value) in raw {
+        for key in keys.split(',').map(str::trim) {
+            res.insert(key.to_string(), value.clone());
+        }
+    }
+    res
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{bail, Result};
+use crossbeam_channel::{self as channel, Receiver, Sender};
+
+const WORKERS: usize = 4;
+
+/// A finished computation of a directory size.
+pub struct DirSize {
+    pub path: PathBuf,
+    generation: u64,
+    pub size: u64,
+}
+
+/// Recursive directory sizes, computed on background threads and cached.
+///
+/// The watcher only sees changes to the current directory, so cached sizes
+/// are shown until they are recomputed on the next visit.
+pub struct DirSizes {
+    sizes: HashMap<PathBuf, u64>,
+
+    /// Directories being computed. A result is only accepted if its
+    /// generation matches, so sizes invalidated in the meantime are dropped.
+    pending: HashMap<PathBuf, u64>,
+    generation: u64,
+    /// Jobs older than this generation were cancelled, and their walks give up.
+    oldest: Arc<AtomicU64>,
+    jobs: Sender<(PathBuf, u64)>,
+    queued: Receiver<(PathBuf, u64)>,
+}
+
+impl DirSizes {
+    pub fn new() -> (DirSizes, Receiver<DirSize>) {
+        let (jobs, job_rx) = channel::unbounded::<(PathBuf, u64)>();
+        let (tx, rx) = channel::unbounded();
+        let oldest = Arc::new(AtomicU64::new(0));
+        for _ in 0..WORKERS {
+            let job_rx = job_rx.clone();
+            let tx = tx.clone();
+            let oldest = Arc::clone(&oldest);
+            thread::spawn(move || {
+                for (path, generation) in job_rx {
+                    let res = walk(&path, &mut |_, metadata, children| {
+                        if generation < oldest.load(Ordering::Relaxed) {
+                            bail!("cleared");
+                        }
+                        Ok(size(metadata, children))
+                    });
+                    let size = match res {
+                        Ok(size) => size.unwrap_or(0),
+                        Err(_) => continue,
+                    };
+                    let result = DirSize {
+                        path,
+                        generation,
+                        size,
+                    };
+                    if tx.send(result).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        let dir_sizes = DirSizes {
+            sizes: HashMap::new(),
+            pending: HashMap::new(),
+            generation: 0,
+            oldest,
+            jobs,
+            queued: job_rx,
+        };
+        (dir_sizes, rx)
+    }
+
+    pub fn get(&self, dir: &Path) -> Option<u64> {
+        self.sizes.get(dir).copied()
+    }
+
+    /// Compute the size of `dir` unless it is being computed, or cached and not `stale`.
+    pub fn request(&mut self, dir: &Path, stale: bool) {
+        if (!stale && self.sizes.contains_key(dir)) || self.pending.contains_key(dir) {
+            return;
+        }
+        self.generation += 1;
+        self.pending.insert(dir.to_owned(), self.generation);
+        let _ = self.jobs.send((dir.to_owned(), self.generation));
+    }
+
+    /// Cache a size computed elsewhere.
+    pub fn insert(&mut self, dir: &Path, size: u64) {
+        self.pending.remove(dir);
+        self.sizes.insert(dir.to_owned(), size);
+    }
+
+    /// Returns whether the result is still wanted.
+    pub fn on_computed(&mut self, result: DirSize) -> bool {
+        if self.pending.get(&result.path) != Some(&result.generation) {
+            return false;
+        }
+        self.pending.remove(&result.path);
+        self.sizes.insert(result.path, result.size);
+        true
+    }
+
+    /// Forget the sizes of `path` and all directories containing it.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.sizes.retain(|dir, _| !path.starts_with(dir));
+        self.pending.retain(|dir, _| !path.starts_with(dir));
+    }
+
+    /// Stop computing the sizes not done yet, keeping the cached ones.
+    pub fn cancel(&mut self) {
+        self.oldest.store(self.generation + 1, Ordering::Relaxed);
+        while self.queued.try_recv().is_ok() {}
+        self.pending.clear();
+    }
+}
+
+/// Size of a file, or of everything under a directory. Symlinks are not followed.
+pub fn total_size(path: &Path) -> u64 {
+    walk(path, &mut |_, metadata, children: Vec<u64>| {
+        Ok(size(metadata, children))
+    })
+    .ok()
+    .flatten()
+    .unwrap_or(0)
+}
+
+/// Size of a file, or the sum of the sizes of a directory's children.
+pub fn size(metadata: &Metadata, children: impl IntoIterator<Item = u64>) -> u64 {
+    if metadata.is_dir() {
+        children.into_iter().sum()
+    } else {
+        metadata.len()
+    }
+}
+
+/// Walk `path` and everything under it without following symlinks, calling `visit`
+/// on each file with what it returned for the file's children. Files that can't be
+/// read are left out, and the walk stops at the first error returned by `visit`.
+pub fn walk<T, F>(path: &Path, visit: &mut F) -> Result<Option<T>>
+where
+    F: FnMut(&Path, &Metadata, Vec<T>) -> Result<T>,
+{
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(None),
+    };
+    let mut children = vec![];
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            children.extend(walk(&entry.path(), visit)?);
+        }
+    }
+    visit(path, &metadata, children).map(Some)
+}
@@ -0,0 +1,281 @@
+use std::cmp::Reverse;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use termion::event::Key;
+use tui::backend::Backend;
+use tui::layout::{Alignment, Rect};
+use tui::style::{Color, Style};
+use tui::widgets::{Clear, List, ListState, Paragraph, Text};
+use tui::Frame;
+
+use crate::app::ListExt;
+use crate::dir_sizes::{size, walk};
+use crate::popup;
+use crate::status_bar::{format_size, StatusBar};
+use crate::task_manager::{Progress, TaskManager};
+
+const BAR_WIDTH: usize = 10;
+
+pub struct Node {
+    pub name: OsString,
+    pub size: u64,
+    pub is_dir: bool,
+    /// Sorted by size, largest first.
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn scan(path: &Path, name: OsString, progress: &Progress, count: &mut u64) -> Result<Node> {
+        let node = walk(path, &mut |path, metadata, mut children: Vec<Node>| {
+            progress.check()?;
+            *count += 1;
+            if count.is_multiple_of(1000) {
+                progress.update(format!("{} files", count));
+            }
+            children.sort_by_key(|c| Reverse(c.size));
+            Ok(Node {
+                name: path.file_name().unwrap_or_default().to_owned(),
+                size: size(metadata, children.iter().map(|c| c.size)),
+                is_dir: metadata.is_dir(),
+                children,
+            })
+        })?;
+        Ok(Node {
+            name,
+            ..node.unwrap_or(Node {
+                name: OsString::new(),
+                size: 0,
+                is_dir: false,
+                children: vec![],
+            })
+        })
+    }
+
+    fn get(&self, path: &[OsString]) -> Option<&Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((name, rest)) => self
+                .children
+                .iter()
+                .find(|c| &c.name == name)
+                .and_then(|c| c.get(rest)),
+        }
+    }
+
+    /// Remove the node at `path`, returning its size.
+    fn remove(&mut self, path: &[OsString]) -> u64 {
+        let size = match path {
+            [] => 0,
+            [name] => match self.children.iter().position(|c| &c.name == name) {
+                Some(index) => self.children.remove(index).size,
+                None => 0,
+            },
+            [name, rest @ ..] => match self.children.iter_mut().find(|c| &c.name == name) {
+                Some(child) => child.remove(rest),
+                None => 0,
+            },
+        };
+        self.size -= size;
+        size
+    }
+}
+
+/// An ncdu-like view of the sizes under a directory, drawn over the file list.
+pub struct DiskUsage {
+    root: PathBuf,
+    scanned: Arc<Mutex<Option<Result<Node, String>>>>,
+    tree: Option<Node>,
+    error: Option<String>,
+
+    /// The directory being viewed, with the selection of each level above it.
+    path: Vec<(OsString, Option<usize>)>,
+    list_state: ListState,
+}
+
+impl DiskUsage {
+    /// Start scanning `root` as a task.
+    pub fn scan(root: PathBuf, task_manager: &mut TaskManager) -> DiskUsage {
+        let scanned = Arc::new(Mutex::new(None));
+        task_manager.spawn(format!("Disk usage of {}", root.display()), {
+            let root = root.clone();
+            let scanned = scanned.clone();
+            move |progress| {
+                let mut count = 0;
+                match Node::scan(&root, OsString::new(), progress, &mut count) {
+                    Ok(tree) => {
+                        *scanned.lock().unwrap() = Some(Ok(tree));
+                        Ok(())
+                    }
+                    Err(e) => {
+                        *scanned.lock().unwrap() = Some(Err(e.to_string()));
+                        Err(e)
+                    }
+                }
+            }
+        });
+        DiskUsage {
+            root,
+            scanned,
+            tree: None,
+            error: None,
+            path: vec![],
+            list_state: ListState::default(),
+        }
+    }
+
+    /// Take the result once the scan finishes.
+    fn poll(&mut self) {
+        if self.tree.is_some() || self.error.is_some() {
+            return;
+        }
+        let scanned = self.scanned.lock().unwrap().take();
+        if let Some(res) = scanned {
+            match res {
+                Ok(tree) => self.tree = Some(tree),
+                Err(e) => self.error = Some(e),
+            }
+            self.select_first();
+        }
+    }
+
+    fn names(&self) -> Vec<OsString> {
+        self.path.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn current(&self) -> Option<&Node> {
+        self.tree.as_ref().and_then(|t| t.get(&self.names()))
+    }
+
+    fn dir(&self) -> PathBuf {
+        let mut dir = self.root.clone();
+        dir.extend(self.path.iter().map(|(name, _)| name));
+        dir
+    }
+
+    /// Returns `true` when the view should be closed.
+    pub fn on_key(&mut self, key: Key, status_bar: &mut StatusBar) -> Result<bool> {
+        self.poll();
+        match key {
+            Key::Esc | Key::Char('U') => return Ok(true),
+            Key::Char('l') | Key::Char('\n') | Key::Right => {
+                if let Some(node) = self.selected().filter(|n| n.is_dir) {
+                    let name = node.name.clone();
+                    let selected = self.list_state.selected();
+                    self.path.push((name, selected));
+                    self.select_first();
+                }
+            }
+            Key::Char('h') | Key::Backspace | Key::Left => {
+                if let Some((_, selected)) = self.path.pop() {
+                    self.list_state.select(selected);
+                }
+            }
+            Key::Char('d') => {
+                if let Some(node) = self.selected() {
+                    let mut names = self.names();
+                    names.push(node.name.clone());
+                    let file = self.dir().join(&node.name);
+                    status_bar.ask(
+                        format!(
+                            "Move {} ({}) to the trash? [y/N]",
+                            node.name.to_string_lossy(),
+                            format_size(node.size)
+                        ),
+                        move |this, _, status_bar| {
+                            this.trash(std::slice::from_ref(&file), status_bar)?;
+                            if let Some(disk_usage) = &mut this.disk_usage {
+                                disk_usage.remove(&names);
+                            }
+                            Ok(())
+                        },
+                    );
+                }
+            }
+            key => self.on_list_key(key)?,
+        }
+        Ok(false)
+    }
+
+    fn remove(&mut self, names: &[OsString]) {
+        if let Some(tree) = &mut self.tree {
+            tree.remove(names);
+        }
+        let len = self.get_list().len();
+        match self.list_state.selected() {
+            _ if len == 0 => self.select(None),
+            Some(index) if index >= len => self.select(Some(len - 1)),
+            _ => {}
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame<impl Backend>, area: Rect) {
+        self.poll();
+        let title = match self.current() {
+            Some(node) => format!(" {} {} ", self.dir().display(), format_size(node.size)),
+            None => format!(" {} ", self.root.display()),
+        };
+        let block = popup::block(&title);
+        frame.render_widget(Clear, area);
+
+        let node = match (self.current(), &self.error) {
+            (Some(node), _) => node,
+            (None, error) => {
+                let text = match error {
+                    Some(error) => format!("Scan failed: {}", error),
+                    None => "Scanning...".to_string(),
+                };
+                let text = [Text::raw(text)];
+                let paragraph = Paragraph::new(text.iter())
+                    .block(block)
+                    .alignment(Alignment::Center);
+                frame.render_widget(paragraph, area);
+                return;
+            }
+        };
+
+        let total = node.size.max(1);
+        let items: Vec<String> = node
+            .children
+            .iter()
+            .map(|child| {
+                let ratio = child.size as f64 / total as f64;
+                let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+                format!(
+                    "{:>7} {:5.1}% [{}{}] {}{}",
+                    format_size(child.size),
+                    ratio * 100.0,
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    child.name.to_string_lossy(),
+                    if child.is_dir { "/" } else { "" }
+                )
+            })
+            .collect();
+        let list = List::new(items.iter().map(Text::raw))
+            .block(block)
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::Blue));
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+}
+
+impl ListExt for DiskUsage {
+    type Item = Node;
+
+    fn get_index(&self) -> Option<usize> {
+        self.list_state.selected()
+    }
+
+    fn get_list(&self) -> &[Self::Item] {
+        match self.current() {
+            Some(node) => &node.children,
+            None => &[],
+        }
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        self.list_state.select(index)
+    }
+}
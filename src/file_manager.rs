@@ -1,36 +1,130 @@
+use std::borrow::Cow;
 use std::cmp;
 use std::convert::TryFrom;
 use std::env;
-use std::fs::{self, DirEntry, Metadata};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirEntry, OpenOptions};
 use std::io;
 use std::mem;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use crossbeam_channel::{self as channel, Receiver};
+use glob::Pattern;
 use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use termion::event::Key;
 use tui::backend::Backend;
-use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::widgets::{List, ListState, Paragraph, Text};
+use tui::widgets::{ListState, Paragraph, Text};
 use tui::Frame;
 
 use crate::app::ListExt;
+use crate::archive::{self, Archive};
+use crate::bookmarks::Bookmarks;
+use crate::bulk_rename::{self, BulkRename};
+use crate::config;
+use crate::dir_sizes::{self, DirSize, DirSizes};
+use crate::disk_usage::DiskUsage;
+use crate::file_ops::{self, Paste};
+use crate::git::{FileStatus, GitLoader, GitStatus, LoadedStatus};
+use crate::icons::Icons;
+use crate::journal::{Change, Journal, Step};
+use crate::permissions::PermissionEditor;
+use crate::popup::{Popup, PopupItem, PopupKind};
+use crate::search::Search;
 use crate::shell;
-use crate::status_bar::StatusBar;
+use crate::status_bar::{format_size, StatusBar};
+use crate::task_manager::TaskManager;
+use crate::theme::Theme;
+use crate::trash;
+use nix::sys::stat::SFlag;
 use nix::unistd::Pid;
+use tempfile::TempDir;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub path: PathBuf,
-    pub name: String,
-    pub extension: Option<String>,
+    pub name: OsString,
+    pub extension: Option<OsString>,
+    /// Metadata of the file, or of the file a symlink points to if it's not broken.
     pub metadata: Metadata,
+    pub symlink: Option<Symlink>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symlink {
+    /// The content of the link, may be relative to the link's directory.
+    pub target: PathBuf,
+    pub broken: bool,
+}
+
+/// The metadata shown for a file, which can also describe an archive member.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    mode: u32,
+    len: u64,
+}
+
+impl Metadata {
+    pub fn new(mode: u32, len: u64) -> Metadata {
+        Metadata { mode, len }
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn file_type(&self) -> SFlag {
+        SFlag::from_bits_truncate(self.mode) & SFlag::S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == SFlag::S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == SFlag::S_IFREG
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        self.file_type() == SFlag::S_IFIFO
+    }
+
+    pub fn is_socket(&self) -> bool {
+        self.file_type() == SFlag::S_IFSOCK
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        self.file_type() == SFlag::S_IFBLK
+    }
+
+    pub fn is_char_device(&self) -> bool {
+        self.file_type() == SFlag::S_IFCHR
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Metadata {
+        Metadata::new(metadata.mode(), metadata.len())
+    }
+}
+
+impl FileInfo {
+    /// The name decoded lossily, only for display.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        self.name.to_string_lossy()
+    }
 }
 
 impl TryFrom<DirEntry> for FileInfo {
@@ -38,87 +132,298 @@ impl TryFrom<DirEntry> for FileInfo {
 
     fn try_from(entry: DirEntry) -> Result<Self, Self::Error> {
         let path = entry.path();
-        let name = entry.file_name().to_str().unwrap().to_owned();
-        let extension = path.extension().map(|e| e.to_str().unwrap().to_owned());
+        let name = entry.file_name();
+        let extension = path.extension().map(OsStr::to_owned);
+        let mut metadata = entry.metadata()?;
+        let mut symlink = None;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path)?;
+            let broken = match fs::metadata(&path) {
+                Ok(target_metadata) => {
+                    metadata = target_metadata;
+                    false
+                }
+                Err(_) => true,
+            };
+            symlink = Some(Symlink { target, broken });
+        }
         Ok(Self {
             path,
             name,
             extension,
-            metadata: entry.metadata()?,
+            metadata: metadata.into(),
+            symlink,
         })
     }
 }
 
+/// Cursor position in a directory, restored when it's revisited.
+struct Position {
+    selected: OsString,
+    offset: usize,
+}
+
+/// Visual mode marks every file between the anchor and the cursor.
+pub struct Visual {
+    anchor: usize,
+    marked_before: Vec<PathBuf>,
+}
+
+/// Browser-style back/forward stacks of visited directories.
+#[derive(Default)]
+struct History {
+    back: Vec<PathBuf>,
+    forward: Vec<PathBuf>,
+}
+
 pub struct FileManager<W = RecommendedWatcher>
 where
     W: Watcher,
 {
     dir: PathBuf,
+    /// The archive `dir` is in, when browsing one.
+    archive: Option<Archive>,
     all_files: Vec<FileInfo>,
     pub files: Vec<FileInfo>, // filtered
     pub files_marked: Vec<PathBuf>,
+    pub visual: Option<Visual>,
     pub filter: String,
     show_hidden: bool,
     pub list_state: ListState,
+    offset: usize,
+    positions: HashMap<PathBuf, Position>,
+    history: History,
+    bulk_rename: Option<BulkRename>,
+    journal: Journal,
+    /// A file about to be created, selected once the watcher sees it.
+    select_when_created: Option<OsString>,
+    /// Prefix of the last type-ahead jump, repeated by `;` and `,`.
+    jump_prefix: String,
+    pub popup: Option<Popup>,
+    pub disk_usage: Option<DiskUsage>,
+    pub permissions: Option<PermissionEditor>,
+    search: Option<Search>,
     watcher: W,
     pub shell_pid: Pid,
+    /// Directories the shell was told to enter, whose change events are only echoes.
+    shell_cds: VecDeque<PathBuf>,
     open_methods: HashMap<String, String>,
+    git: Option<GitStatus>,
+    git_loader: GitLoader,
+    /// Holds the archive members extracted to be opened.
+    preview_dir: Option<TempDir>,
+    icons: Icons,
+    theme: Theme,
+    dir_sizes: DirSizes,
 }
 
 impl<W> FileManager<W>
 where
     W: Watcher,
 {
-    pub fn new() -> Result<(FileManager<W>, Receiver<notify::Event>)> {
+    #[allow(clippy::type_complexity)]
+    pub fn new() -> Result<(
+        FileManager<W>,
+        Receiver<notify::Event>,
+        Receiver<DirSize>,
+        Receiver<LoadedStatus>,
+    )> {
         let (tx, rx) = channel::bounded(0);
         let watcher = W::new_immediate(move |event: notify::Result<notify::Event>| {
             tx.send(event.unwrap()).unwrap()
         })?;
+        let (dir_sizes, dir_size_rx) = DirSizes::new();
+        let (git_loader, git_rx) = GitLoader::new();
 
         let mut file_manager = FileManager {
             dir: PathBuf::new(),
+            archive: None,
             all_files: vec![],
             files: vec![],
             files_marked: vec![],
+            visual: None,
             filter: "".to_string(),
             show_hidden: false,
             list_state: ListState::default(),
+            offset: 0,
+            positions: HashMap::new(),
+            history: History::default(),
+            bulk_rename: None,
+            journal: Journal::default(),
+            select_when_created: None,
+            jump_prefix: String::new(),
+            popup: None,
+            disk_usage: None,
+            permissions: None,
+            search: None,
             watcher,
             shell_pid: Pid::from_raw(0),
+            shell_cds: VecDeque::new(),
             open_methods: load_open_methods()?,
+            git: None,
+            git_loader,
+            preview_dir: None,
+            icons: Icons::load()?,
+            theme: Theme::load()?,
+            dir_sizes,
         };
         file_manager.cd(env::current_dir()?)?;
 
-        Ok((file_manager, rx))
+        Ok((file_manager, rx, dir_size_rx, git_rx))
     }
 
-    pub fn cd(&mut self, mut dir: PathBuf) -> Result<()> {
+    /// Change to `dir` and record it in the history.
+    pub fn cd(&mut self, dir: PathBuf) -> Result<()> {
+        let old = self.dir.clone();
+        if self.load_dir(dir)? && old != Path::new("") {
+            self.history.back.push(old);
+            self.history.forward.clear();
+        }
+        Ok(())
+    }
+
+    /// Change to `dir` and let the shell follow.
+    pub fn cd_with_shell(&mut self, dir: PathBuf) -> Result<()> {
+        self.cd(dir)?;
+        self.shell_cd(self.shell_dir().to_owned())
+    }
+
+    /// Make the shell enter `dir`, without following it again when it reports back.
+    fn shell_cd(&mut self, dir: PathBuf) -> Result<()> {
+        shell::run(self.shell_pid, "cd", &[&dir], false)?;
+        self.shell_cds.push_back(dir);
+        Ok(())
+    }
+
+    /// Whether git doesn't track `path`, as far as the status of the current directory tells.
+    fn is_untracked(&self, path: &Path) -> bool {
+        match (&self.git, path.file_name()) {
+            (Some(git), Some(name)) if path.parent() == Some(&self.dir) => matches!(
+                git.get(name),
+                Some(FileStatus::Untracked) | Some(FileStatus::Ignored)
+            ),
+            _ => false,
+        }
+    }
+
+    /// The directory the shell follows, which is the one containing the archive
+    /// when browsing an archive.
+    fn shell_dir(&self) -> &Path {
+        match &self.archive {
+            Some(archive) => archive.path.parent().unwrap_or(&self.dir),
+            None => &self.dir,
+        }
+    }
+
+    /// The path watched for changes: the current directory, or the archive it's in.
+    fn watched(&self) -> &Path {
+        match &self.archive {
+            Some(archive) => &archive.path,
+            None => &self.dir,
+        }
+    }
+
+    /// Open the archive containing the current directory, unless it's open already.
+    fn load_archive(&mut self) -> io::Result<()> {
+        match archive::split(&self.dir) {
+            Some((path, _)) if self.archive.as_ref().is_some_and(|a| a.path == path) => {}
+            Some((path, _)) => self.archive = Some(Archive::open(path)?),
+            None => self.archive = None,
+        }
+        Ok(())
+    }
+
+    /// Go back in the history, or forward if `back` is false.
+    fn travel(&mut self, back: bool, status_bar: &mut StatusBar) -> Result<()> {
+        let stack = if back {
+            &mut self.history.back
+        } else {
+            &mut self.history.forward
+        };
+        match stack.pop() {
+            Some(dir) => {
+                let old = self.dir.clone();
+                self.load_dir(dir)?;
+                if back {
+                    self.history.forward.push(old);
+                } else {
+                    self.history.back.push(old);
+                }
+                self.shell_cd(self.shell_dir().to_owned())?;
+            }
+            None if back => status_bar.show_message("Already at the oldest directory"),
+            None => status_bar.show_message("Already at the newest directory"),
+        }
+        Ok(())
+    }
+
+    /// Returns whether the directory was changed.
+    fn load_dir(&mut self, mut dir: PathBuf) -> Result<bool> {
         if dir != self.dir {
             if self.dir != Path::new("") {
-                self.watcher.unwatch(&self.dir)?;
+                let watched = self.watched().to_owned();
+                self.watcher.unwatch(&watched)?;
             }
+            let position = self.selected().map(|f| Position {
+                selected: f.name.clone(),
+                offset: self.offset,
+            });
             mem::swap(&mut self.dir, &mut dir);
-            match self.read_dir() {
+            self.visual = None;
+            self.select_when_created = None;
+            match self.load_archive().and_then(|_| self.read_dir()) {
                 Ok(res) => {
+                    if let Some(position) = position {
+                        self.positions.insert(dir.clone(), position);
+                    }
                     self.all_files = res;
                     self.apply_filter();
-                    self.select_first();
-                    self.watcher.watch(&self.dir, RecursiveMode::NonRecursive)?;
+                    self.restore_position(&dir);
+                    self.git = None;
+                    self.refresh_git();
+                    // Sizes cached on an earlier visit may have changed since.
+                    self.dir_sizes.cancel();
+                    self.request_dir_sizes(true);
+                    let watched = self.watched().to_owned();
+                    self.watcher.watch(&watched, RecursiveMode::NonRecursive)?;
                 }
                 Err(e) => {
                     self.dir = dir;
-                    self.watcher.watch(&self.dir, RecursiveMode::NonRecursive)?;
+                    let _ = self.load_archive();
+                    let watched = self.watched().to_owned();
+                    self.watcher.watch(&watched, RecursiveMode::NonRecursive)?;
                     return Err(e.into());
                 }
             }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Select the child we came from, or the entry selected on the last visit.
+    fn restore_position(&mut self, from: &Path) {
+        let child = from
+            .strip_prefix(&self.dir)
+            .ok()
+            .and_then(|p| p.iter().next())
+            .map(OsStr::to_owned);
+        let position = self.positions.get(&self.dir);
+        self.offset = position.map_or(0, |p| p.offset);
+        match child.or_else(|| position.map(|p| p.selected.clone())) {
+            Some(name) => self.select_file(&name),
+            None => self.select_first(),
         }
-        Ok(())
     }
 
     pub fn read_dir(&self) -> io::Result<Vec<FileInfo>> {
         let mut res = vec![];
-        for entry in fs::read_dir(&self.dir)? {
-            res.push(FileInfo::try_from(entry?)?);
+        match &self.archive {
+            Some(archive) => res = archive.read_dir(&self.dir)?,
+            None => {
+                for entry in fs::read_dir(&self.dir)? {
+                    res.push(FileInfo::try_from(entry?)?);
+                }
+            }
         }
         res.sort_unstable_by(|a, b| match (a.metadata.is_dir(), b.metadata.is_dir()) {
             (true, false) => cmp::Ordering::Less,
@@ -133,71 +438,630 @@ where
         self.files = self
             .all_files
             .iter()
-            .filter(|f| self.show_hidden || !f.name.starts_with('.'))
-            .filter(|f| f.name.to_lowercase().contains(&self.filter.to_lowercase()))
+            .filter(|f| self.show_hidden || !f.name.as_bytes().starts_with(b"."))
+            .filter(|f| {
+                f.display_name()
+                    .to_lowercase()
+                    .contains(&self.filter.to_lowercase())
+            })
             .cloned()
             .collect();
 
         // Keep selection after filter.
         if let Some(name) = selected {
-            self.select_file(name);
+            self.select_file(&name);
+        }
+    }
+
+    pub fn select_file(&mut self, name: &OsStr) {
+        match self.files.iter().position(|f| f.name == name) {
+            Some(index) => self.list_state.select(Some(index)),
+            None => self.select_first(),
+        }
+    }
+
+    /// Move to the next (or previous) file whose name starts with `jump_prefix`,
+    /// wrapping around. The selected file counts as a match unless `skip_selected`.
+    fn jump(&mut self, forward: bool, skip_selected: bool) -> bool {
+        let len = self.files.len();
+        let start = self.list_state.selected().unwrap_or(0);
+        let prefix = self.jump_prefix.to_lowercase();
+        let found = (0..len)
+            .map(|i| i + skip_selected as usize)
+            .map(|i| {
+                if forward {
+                    (start + i) % len
+                } else {
+                    (start + len * 2 - i) % len
+                }
+            })
+            .find(|&i| {
+                self.files[i]
+                    .display_name()
+                    .to_lowercase()
+                    .starts_with(&prefix)
+            });
+        if let Some(index) = found {
+            self.list_state.select(Some(index));
+        }
+        found.is_some()
+    }
+
+    fn mark(&mut self, path: &Path) {
+        if !self.files_marked.iter().any(|p| p == path) {
+            self.files_marked.push(path.to_owned());
+        }
+    }
+
+    fn unmark(&mut self, path: &Path) {
+        self.files_marked.retain(|p| p != path);
+    }
+
+    /// Mark or unmark the visible files whose names match `pattern`.
+    fn mark_by_glob(&mut self, pattern: &str, mark: bool) -> Result<()> {
+        let pattern = Pattern::new(pattern)?;
+        let paths: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|f| pattern.matches(&f.display_name()))
+            .map(|f| f.path.clone())
+            .collect();
+        for path in &paths {
+            if mark {
+                self.mark(path);
+            } else {
+                self.unmark(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmark all files, ending visual mode since its range was used up too.
+    pub fn clear_marks(&mut self) {
+        self.files_marked.clear();
+        self.visual = None;
+    }
+
+    fn update_visual(&mut self) {
+        if let (Some(visual), Some(cursor)) = (&self.visual, self.list_state.selected()) {
+            let (start, end) = if visual.anchor < cursor {
+                (visual.anchor, cursor)
+            } else {
+                (cursor, visual.anchor)
+            };
+            let mut marked = visual.marked_before.clone();
+            for file in self.files.iter().take(end + 1).skip(start) {
+                if !marked.contains(&file.path) {
+                    marked.push(file.path.clone());
+                }
+            }
+            self.files_marked = marked;
+        }
+    }
+
+    /// Select the entry of the current directory that will contain `path` once it is created.
+    fn select_later(&mut self, path: &Path) {
+        let path = self.dir.join(path);
+        self.select_when_created = path
+            .strip_prefix(&self.dir)
+            .ok()
+            .and_then(|p| p.iter().next())
+            .map(OsStr::to_owned);
+    }
+
+    /// Open files with their open methods, grouping files opened by the same command.
+    ///
+    /// `{line}` in the commands is replaced with `line`, or 1 if not given.
+    fn open(&self, files: Vec<PathBuf>, line: Option<usize>) -> Result<()> {
+        let mut groups: Vec<(&str, Vec<PathBuf>)> = vec![];
+        for path in files {
+            let open_cmd = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| self.open_methods.get(ext))
+                .map(String::as_str)
+                .unwrap_or("xdg-open");
+            match groups.iter_mut().find(|(cmd, _)| *cmd == open_cmd) {
+                Some((_, paths)) => paths.push(path),
+                None => groups.push((open_cmd, vec![path])),
+            }
+        }
+        for (open_cmd, paths) in groups {
+            let open_cmd = open_cmd.replace("{line}", &line.unwrap_or(1).to_string());
+            // Only commands written with `{*}` take many files at once.
+            if open_cmd.contains("{*}") {
+                shell::run(self.shell_pid, &open_cmd.replace("{*}", "{}"), &paths, true)?;
+            } else {
+                shell::run_each(self.shell_pid, &open_cmd, &paths, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The marked files, or the selected file if nothing is marked.
+    pub fn targets(&self) -> Vec<PathBuf> {
+        if self.files_marked.is_empty() {
+            self.selected()
+                .map(|f| f.path.clone())
+                .into_iter()
+                .collect()
+        } else {
+            self.files_marked.clone()
         }
     }
 
-    pub fn select_file(&mut self, name: String) {
-        let index = self.files.iter().position(|f| f.name == name).unwrap_or(0);
-        self.list_state.select(Some(index));
+    /// `path` as a member of the current archive, if it is inside it.
+    fn member(&self, path: &Path) -> Option<PathBuf> {
+        let archive = &self.archive.as_ref()?.path;
+        path.strip_prefix(archive)
+            .ok()
+            .filter(|member| member != &Path::new(""))
+            .map(Path::to_owned)
+    }
+
+    /// The marked members of the current archive, or the selected one if none are marked.
+    /// Marks outside of the archive are left out.
+    fn members(&self) -> Vec<PathBuf> {
+        let marked: Vec<PathBuf> = self
+            .files_marked
+            .iter()
+            .filter_map(|path| self.member(path))
+            .collect();
+        if !marked.is_empty() {
+            return marked;
+        }
+        self.selected()
+            .and_then(|file| self.member(&file.path))
+            .into_iter()
+            .collect()
+    }
+
+    /// Extract the selected archive member to a temporary directory and open it.
+    fn preview(&mut self) -> Result<()> {
+        let archive = match &self.archive {
+            Some(archive) => archive.path.clone(),
+            None => return Ok(()),
+        };
+        if self.preview_dir.is_none() {
+            let preview_dir = tempfile::Builder::new().prefix("scd-preview-").tempdir()?;
+            fs::set_permissions(preview_dir.path(), fs::Permissions::from_mode(0o700))?;
+            self.preview_dir = Some(preview_dir);
+        }
+        // A new directory for each preview, removed with the others when scd exits.
+        let dir = match &self.preview_dir {
+            Some(preview_dir) => tempfile::tempdir_in(preview_dir.path())?.into_path(),
+            None => return Ok(()),
+        };
+        let member = match self.selected().and_then(|file| self.member(&file.path)) {
+            Some(member) => member,
+            None => return Ok(()),
+        };
+        let file = dir.join(member.file_name().unwrap_or_default());
+        archive::extract(&archive, &[member], &dir, true, |_, _| Ok(()))?;
+        if file.is_file() {
+            self.open(vec![file], None)?;
+        }
+        Ok(())
+    }
+
+    /// Load the git status of the current directory in the background.
+    pub fn refresh_git(&mut self) {
+        match self.archive {
+            Some(_) => self.git = None,
+            None => self.git_loader.request(&self.dir),
+        }
+    }
+
+    pub fn on_git_status(&mut self, loaded: LoadedStatus) {
+        if loaded.dir == self.dir && self.archive.is_none() {
+            self.git = loaded.status;
+        }
     }
 
     pub fn on_notify(&mut self, event: notify::Event) -> io::Result<()> {
         match event.kind {
             EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(_) => {
+                for path in &event.paths {
+                    self.dir_sizes.invalidate(path);
+                }
+                if let Some(archive) = &self.archive {
+                    self.archive = Some(Archive::open(&archive.path)?);
+                }
                 self.read_dir().map(|res| {
                     self.all_files = res;
                     self.apply_filter();
+                    self.refresh_git();
+                    self.request_dir_sizes(false);
+                    if let Some(name) = &self.select_when_created {
+                        if let Some(index) = self.files.iter().position(|f| &f.name == name) {
+                            self.list_state.select(Some(index));
+                            self.select_when_created = None;
+                        }
+                    }
                 })
             }
             _ => Ok(()),
         }
     }
 
-    pub fn on_shell_event(&mut self, shell_event: shell::Event) -> Result<()> {
+    fn request_dir_sizes(&mut self, stale: bool) {
+        if self.archive.is_some() {
+            return;
+        }
+        for file in &self.all_files {
+            if file.symlink.is_none() && file.metadata.is_dir() {
+                self.dir_sizes.request(&file.path, stale);
+            }
+        }
+    }
+
+    pub fn on_dir_size(&mut self, result: DirSize) {
+        self.dir_sizes.on_computed(result);
+    }
+
+    /// Size of a file, or the recursive size of a directory once it is computed.
+    pub fn size_of(&self, file: &FileInfo) -> Option<u64> {
+        if file.symlink.is_none() && file.metadata.is_dir() && self.archive.is_none() {
+            self.dir_sizes.get(&file.path)
+        } else {
+            Some(file.metadata.len())
+        }
+    }
+
+    /// Move `files` to the trash, recording it in the journal.
+    pub fn trash(&mut self, files: &[PathBuf], status_bar: &mut StatusBar) -> Result<()> {
+        let size = self.total_size(files);
+        let mut steps = vec![];
+        let res = files.iter().try_for_each(|file| {
+            steps.push(Step::new(Change::Trash {
+                file: trash::trash(file)?,
+                original: file.clone(),
+            }));
+            Ok::<_, anyhow::Error>(())
+        });
+        self.journal
+            .record(file_ops::task_name("Trash", files, None), steps);
+        res?;
+        match files {
+            [] => {}
+            [file] => status_bar.show_message(format!(
+                "Moved {} ({}) to the trash",
+                file.file_name().unwrap_or_default().to_string_lossy(),
+                size
+            )),
+            _ => status_bar.show_message(format!(
+                "Moved {} files ({}) to the trash",
+                files.len(),
+                size
+            )),
+        }
+        Ok(())
+    }
+
+    /// Total size of `files`, computing the directory sizes not cached yet.
+    fn total_size(&mut self, files: &[PathBuf]) -> String {
+        let mut total = 0;
+        for file in files {
+            match fs::symlink_metadata(file) {
+                Ok(metadata) if metadata.is_dir() => match self.dir_sizes.get(file) {
+                    Some(size) => total += size,
+                    None => {
+                        let size = dir_sizes::total_size(file);
+                        self.dir_sizes.insert(file, size);
+                        total += size;
+                    }
+                },
+                Ok(metadata) => total += metadata.len(),
+                Err(_) => {}
+            }
+        }
+        format_size(total)
+    }
+
+    pub fn on_shell_event(
+        &mut self,
+        shell_event: shell::Event,
+        status_bar: &mut StatusBar,
+    ) -> Result<()> {
         match shell_event {
             shell::Event::Pid(pid) => self.shell_pid = Pid::from_raw(pid),
-            shell::Event::ChangeDirectory(dir) => self.cd(dir)?,
+            // The shell stays next to an archive being browsed.
+            shell::Event::ChangeDirectory(dir) => {
+                // The shell only reports back a cd asked for earlier, which may be stale
+                // already after quickly going back and forth.
+                if let Some(index) = self.shell_cds.iter().position(|d| d == &dir) {
+                    self.shell_cds.remove(index);
+                    return Ok(());
+                }
+                self.shell_cds.clear();
+                // The shell stays next to an archive being browsed.
+                if !(self.archive.is_some() && dir == self.shell_dir()) {
+                    self.cd(dir)?;
+                }
+            }
+            shell::Event::Prompt => self.refresh_git(),
+            shell::Event::Edited(file) => {
+                if let Some(bulk_rename) = self.bulk_rename.as_ref().filter(|b| b.buffer == file) {
+                    let renames = bulk_rename.read()?;
+                    if renames.is_empty() {
+                        status_bar.show_message("No files renamed");
+                        self.bulk_rename = None;
+                    } else {
+                        let items = renames
+                            .iter()
+                            .map(|(old, new)| PopupItem {
+                                label: format!(
+                                    "{} -> {}",
+                                    old.strip_prefix(&self.dir).unwrap_or(old).display(),
+                                    new.strip_prefix(&self.dir).unwrap_or(new).display()
+                                ),
+                                path: old.clone(),
+                                line: None,
+                            })
+                            .collect();
+                        let title = format!("Rename {} file(s)? Enter to confirm", renames.len());
+                        self.popup = Some(Popup::new(PopupKind::Rename, title, items));
+                    }
+                }
+            }
             _ => {}
         }
         Ok(())
     }
 
-    pub fn on_key(&mut self, key: Key, status_bar: &mut StatusBar) -> Result<()> {
+    pub fn on_key(
+        &mut self,
+        key: Key,
+        status_bar: &mut StatusBar,
+        task_manager: &mut TaskManager,
+    ) -> Result<()> {
+        if self.popup.is_some() {
+            return self.on_popup_key(key, status_bar);
+        }
+        if let Some(disk_usage) = &mut self.disk_usage {
+            if disk_usage.on_key(key, status_bar)? {
+                self.disk_usage = None;
+            }
+            return Ok(());
+        }
+        if let Some(permissions) = &mut self.permissions {
+            if permissions.on_key(key, status_bar, task_manager)? {
+                self.permissions = None;
+                if key == Key::Char('\n') {
+                    self.clear_marks();
+                }
+            }
+            return Ok(());
+        }
+        if self.visual.is_some() {
+            if let Key::Char('v') | Key::Esc = key {
+                self.visual = None;
+                return Ok(());
+            }
+        }
+        let was_visual = self.visual.is_some();
+        let cursor = self.list_state.selected();
+        let marked = self.files_marked.clone();
+        self.on_normal_key(key, status_bar, task_manager)?;
+        if self.files_marked != marked {
+            // The key used or changed the marks, which would otherwise be
+            // rebuilt from the ones before visual mode.
+            self.visual = None;
+        } else if !was_visual || self.list_state.selected() != cursor {
+            self.update_visual();
+        }
+        Ok(())
+    }
+
+    fn on_normal_key(
+        &mut self,
+        key: Key,
+        status_bar: &mut StatusBar,
+        task_manager: &mut TaskManager,
+    ) -> Result<()> {
+        if self.archive.is_some() {
+            if let Key::Char(
+                'd' | 'D' | 'r' | 'R' | 'c' | 'n' | 'N' | 'i' | 'I' | 'p' | 'm' | 'Z',
+            ) = key
+            {
+                bail!("Archives are read-only");
+            }
+        }
         match key {
             Key::Char('l') | Key::Char('\n') => {
                 if let Some(file) = self.selected() {
-                    if file.metadata.is_dir() {
-                        let path = file.path.clone();
-                        self.cd(path.clone())?;
-                        shell::run(self.shell_pid, "cd", &[path.to_str().unwrap()], false)?;
+                    if file.metadata.is_dir()
+                        || self.archive.is_none() && archive::is_archive(&file.path)
+                    {
+                        self.cd_with_shell(file.path.clone())?;
+                    } else if self.archive.is_some() {
+                        self.preview()?;
+                    } else {
+                        let files = self.targets().into_iter().filter(|f| !f.is_dir());
+                        self.open(files.collect(), None)?;
+                    }
+                }
+            }
+            Key::Char('x') => {
+                // Extract members of the archive being browsed, or unpack the selected archive.
+                let (archive, members, total, dest) = match &self.archive {
+                    Some(archive) => {
+                        let members = self.members();
+                        if members.is_empty() {
+                            bail!("No members selected");
+                        }
+                        let total = members.iter().map(|m| archive.size(m)).sum();
+                        (
+                            archive.path.clone(),
+                            members,
+                            total,
+                            self.shell_dir().to_owned(),
+                        )
+                    }
+                    None => match self.selected() {
+                        Some(file)
+                            if file.metadata.is_file() && archive::is_archive(&file.path) =>
+                        {
+                            let archive = Archive::open(&file.path)?;
+                            let members = archive.top_level();
+                            let total = members.iter().map(|m| archive.size(m)).sum();
+                            (archive.path, members, total, self.dir.clone())
+                        }
+                        _ => bail!("Not an archive"),
+                    },
+                };
+                // Only the members extracted are unmarked, once extracting starts.
+                let marked: Vec<PathBuf> = match self.archive {
+                    Some(_) => members.iter().map(|m| archive.join(m)).collect(),
+                    None => vec![],
+                };
+                let conflicts = archive::conflicts(&members, &dest);
+                let prompt = match conflicts.as_slice() {
+                    [] => {
+                        for path in &marked {
+                            self.unmark(path);
+                        }
+                        archive::spawn_extract(task_manager, archive, members, total, dest, false);
+                        return Ok(());
+                    }
+                    [file] => format!(
+                        "{} already exists, overwrite? [y/N]",
+                        file.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                    _ => format!("{} files already exist, overwrite? [y/N]", conflicts.len()),
+                };
+                status_bar.ask(prompt, move |this, task_manager, _| {
+                    for path in &marked {
+                        this.unmark(path);
+                    }
+                    let (archive, members) = (archive.clone(), members.clone());
+                    archive::spawn_extract(
+                        task_manager,
+                        archive,
+                        members,
+                        total,
+                        dest.clone(),
+                        true,
+                    );
+                    Ok(())
+                });
+            }
+            Key::Char('Z') => {
+                let files = self.targets();
+                let name = match files.as_slice() {
+                    [] => bail!("No files to pack"),
+                    [file] => file.file_name(),
+                    _ => self.dir.file_name(),
+                };
+                let name = format!("{}.tar.gz", name.unwrap_or_default().to_string_lossy());
+                status_bar.edit(
+                    "Pack into: ",
+                    name,
+                    |_, _, _, _| Ok(()),
+                    move |name, this, task_manager, status_bar| {
+                        let archive = this.dir.join(name);
+                        if !archive::is_archive(&archive) {
+                            bail!("Unsupported archive format, use .tar.gz, .tar.zst or .zip");
+                        }
+                        if fs::symlink_metadata(&archive).is_err() {
+                            this.clear_marks();
+                            archive::spawn_pack(task_manager, archive, files.clone());
+                            return Ok(());
+                        }
+                        let files = files.clone();
+                        status_bar.ask(
+                            format!("{} already exists, overwrite? [y/N]", name),
+                            move |this, task_manager, _| {
+                                this.clear_marks();
+                                archive::spawn_pack(task_manager, archive.clone(), files.clone());
+                                Ok(())
+                            },
+                        );
+                        Ok(())
+                    },
+                );
+            }
+            Key::Char('@') => {
+                if let Some(file) = self.selected() {
+                    if file.symlink.is_none() {
+                        status_bar.show_message("Not a symlink");
                     } else {
-                        let open_cmd = match &file.extension {
-                            None => "xdg-open",
-                            Some(ext) => self
-                                .open_methods
-                                .get(ext)
-                                .map(String::as_str)
-                                .unwrap_or("xdg-open"),
+                        let real_path = fs::canonicalize(&file.path)?;
+                        let (dir, name) = if file.metadata.is_dir() {
+                            (real_path, None)
+                        } else {
+                            let name = real_path.file_name().unwrap().to_owned();
+                            (real_path.parent().unwrap().to_owned(), Some(name))
                         };
-                        shell::run(self.shell_pid, open_cmd, &[&file.name], true)?;
+                        self.cd(dir.clone())?;
+                        if let Some(name) = name {
+                            self.select_file(&name);
+                        }
+                        self.shell_cd(dir)?;
                     }
                 }
             }
             Key::Char('h') | Key::Esc => {
                 if let Some(parent) = self.dir.parent() {
-                    let parent = parent.to_owned();
-                    let current = self.dir.file_name().unwrap().to_str().unwrap().to_owned();
-                    self.cd(parent.clone())?;
-                    self.select_file(current);
-                    shell::run(self.shell_pid, "cd", &[parent.to_str().unwrap()], false)?;
+                    self.cd_with_shell(parent.to_owned())?;
+                }
+            }
+            Key::Char('H') => self.travel(true, status_bar)?,
+            Key::Char('L') => self.travel(false, status_bar)?,
+            Key::Ctrl('o') => {
+                let mut items: Vec<PopupItem> = vec![];
+                for dir in self.history.back.iter().rev() {
+                    if dir != &self.dir && items.iter().all(|item| &item.path != dir) {
+                        items.push(PopupItem {
+                            label: dir.to_string_lossy().into_owned(),
+                            path: dir.clone(),
+                            line: None,
+                        });
+                    }
+                }
+                self.popup = Some(Popup::new(PopupKind::History, "History", items));
+            }
+            Key::Char('b') => {
+                let bookmarks = Bookmarks::load()?;
+                let width = bookmarks.0.keys().map(|n| n.width()).max().unwrap_or(0);
+                let items = bookmarks
+                    .0
+                    .iter()
+                    .map(|(name, dir)| PopupItem {
+                        label: format!("{:w$}  {}", name, dir.display(), w = width),
+                        path: dir.clone(),
+                        line: None,
+                    })
+                    .collect();
+                self.popup = Some(Popup::new(PopupKind::Bookmarks, "Bookmarks", items));
+            }
+            Key::Char('B') => {
+                let dir = self.dir.clone();
+                let name = dir
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned();
+                status_bar.edit(
+                    "Bookmark name: ",
+                    name,
+                    |_, _, _, _| Ok(()),
+                    move |name, _, _, _| Bookmarks::load()?.add(name.to_owned(), dir.clone()),
+                );
+            }
+            Key::Char('\'') => {
+                let bookmarks = Bookmarks::load()?;
+                if bookmarks.0.is_empty() {
+                    status_bar.show_message("No bookmarks");
+                } else {
+                    let names: Vec<&str> = bookmarks.0.keys().map(String::as_str).collect();
+                    status_bar.choose(
+                        format!("Jump to bookmark: {}", names.join(" ")),
+                        move |key, this, _, _| match bookmarks.find(key) {
+                            Some(dir) => this.cd_with_shell(dir.clone()),
+                            None => bail!("No bookmark starting with {}", key),
+                        },
+                    );
                 }
             }
             Key::Char('.') => {
@@ -217,149 +1081,676 @@ where
                     }
                 }
             }
+            Key::Char('v') => {
+                if let Some(anchor) = self.list_state.selected() {
+                    self.visual = Some(Visual {
+                        anchor,
+                        marked_before: self.files_marked.clone(),
+                    });
+                }
+            }
+            Key::Ctrl('a') => {
+                let paths: Vec<PathBuf> = self.files.iter().map(|f| f.path.clone()).collect();
+                for path in &paths {
+                    self.mark(path);
+                }
+            }
+            Key::Char('*') => {
+                let paths: Vec<PathBuf> = self.files.iter().map(|f| f.path.clone()).collect();
+                for path in &paths {
+                    if self.files_marked.contains(path) {
+                        self.unmark(path);
+                    } else {
+                        self.mark(path);
+                    }
+                }
+            }
+            Key::Ctrl('u') => self.clear_marks(),
+            Key::Char('+') | Key::Char('-') => {
+                let mark = key == Key::Char('+');
+                status_bar.edit(
+                    if mark { "Mark: " } else { "Unmark: " },
+                    "",
+                    |_, _, _, _| Ok(()),
+                    move |pattern, this, _, _| this.mark_by_glob(pattern, mark),
+                );
+            }
+            Key::Char('M') => {
+                let items = self
+                    .files_marked
+                    .iter()
+                    .map(|path| PopupItem {
+                        label: path.display().to_string(),
+                        path: path.clone(),
+                        line: None,
+                    })
+                    .collect();
+                self.popup = Some(Popup::new(PopupKind::Marked, "Marked", items));
+            }
             Key::Char('p') => {
                 if self.files_marked.is_empty() {
                     status_bar.show_message("No files marked");
                 } else {
-                    let files = mem::take(&mut self.files_marked);
-                    let files: Vec<&str> = files.iter().map(|f| f.to_str().unwrap()).collect();
-                    shell::run(self.shell_pid, "cp -r {} .", &files, true)?;
+                    let files = self.files_marked.clone();
+                    let paste = Paste::new(files, self.dir.clone(), false, self.journal.clone())?;
+                    if paste.resolve(status_bar, task_manager) {
+                        self.clear_marks();
+                    }
                 }
             }
             Key::Char('m') => {
                 if self.files_marked.is_empty() {
                     status_bar.show_message("No files marked");
                 } else {
-                    let files = mem::take(&mut self.files_marked);
-                    let files: Vec<&str> = files.iter().map(|f| f.to_str().unwrap()).collect();
-                    shell::run(self.shell_pid, "mv {} .", &files, true)?;
+                    let files = self.files_marked.clone();
+                    let paste = Paste::new(files, self.dir.clone(), true, self.journal.clone())?;
+                    if paste.resolve(status_bar, task_manager) {
+                        self.clear_marks();
+                    }
                 }
             }
             Key::Char('d') => {
-                if let Some(selected) = self.selected() {
-                    let tp = if selected.metadata.is_file() {
-                        "file"
-                    } else {
-                        "directory"
-                    };
-                    let file = selected.path.to_str().unwrap().to_owned();
-                    status_bar.ask(
-                        format!("Delete {} {}? [y/N]", tp, selected.name),
-                        move |this, _| shell::run(this.shell_pid, "rm -r", &[&file], true),
-                    );
+                let files = self.targets();
+                let size = self.total_size(&files);
+                let prompt = match self.selected() {
+                    Some(selected) if self.files_marked.is_empty() => format!(
+                        "Move {} ({}) to the trash? [y/N]",
+                        selected.display_name(),
+                        size
+                    ),
+                    _ => format!(
+                        "Move {} marked file(s) ({}) to the trash? [y/N]",
+                        files.len(),
+                        size
+                    ),
+                };
+                if !files.is_empty() {
+                    status_bar.ask(prompt, move |this, _, status_bar| {
+                        this.clear_marks();
+                        this.trash(&files, status_bar)
+                    });
+                }
+            }
+            Key::Char('T') => {
+                let items: Vec<PopupItem> = trash::list()?
+                    .into_iter()
+                    .map(|item| PopupItem {
+                        label: format!(
+                            "{}  {}",
+                            item.deleted.replace('T', " "),
+                            item.original.display()
+                        ),
+                        path: item.file,
+                        line: None,
+                    })
+                    .collect();
+                let title = format!("Trash ({})", items.len());
+                self.popup = Some(Popup::new(PopupKind::Trash, title, items));
+            }
+            Key::Char('D') => {
+                let files = self.targets();
+                let size = self.total_size(&files);
+                let prompt = match self.selected() {
+                    Some(selected) if self.files_marked.is_empty() => {
+                        let tp = if selected.metadata.is_file() {
+                            "file"
+                        } else {
+                            "directory"
+                        };
+                        format!(
+                            "Permanently delete {} {} ({})? [y/N]",
+                            tp,
+                            selected.display_name(),
+                            size
+                        )
+                    }
+                    _ => format!(
+                        "Permanently delete {} marked file(s) ({})? [y/N]",
+                        files.len(),
+                        size
+                    ),
+                };
+                if !files.is_empty() {
+                    status_bar.ask(prompt, move |this, task_manager, _| {
+                        this.clear_marks();
+                        file_ops::spawn_delete(task_manager, files.clone());
+                        Ok(())
+                    });
+                }
+            }
+            Key::Char('c') => {
+                let files = self.targets();
+                if !files.is_empty() {
+                    self.permissions = Some(PermissionEditor::new(files)?);
                 }
             }
             Key::Char('r') => {
                 if let Some(file) = self.selected() {
                     let path = file.path.clone();
+                    let old_name = file.display_name().into_owned();
                     status_bar.edit(
                         "Rename: ",
-                        &file.name,
-                        |_, _, _| Ok(()),
-                        move |new_name, this, _| {
-                            shell::run(
-                                this.shell_pid,
-                                "mv",
-                                &[
-                                    path.to_str().unwrap(),
-                                    path.with_file_name(new_name).to_str().unwrap(),
-                                ],
-                                true,
-                            )
+                        file.display_name(),
+                        |_, _, _, _| Ok(()),
+                        move |new_name, this, _, _| {
+                            // The prompt shows a lossy name, which must not replace the real one.
+                            if new_name == old_name {
+                                return Ok(());
+                            }
+                            if new_name.is_empty() {
+                                bail!("Name can't be empty");
+                            }
+                            let new_path = path.with_file_name(new_name);
+                            if new_path == path {
+                                return Ok(());
+                            }
+                            if fs::symlink_metadata(&new_path).is_ok() {
+                                bail!("{} already exists", new_name);
+                            }
+                            fs::rename(&path, &new_path)?;
+                            this.select_later(&new_path);
+                            this.journal.record(
+                                format!("Rename {} to {}", old_name, new_name),
+                                vec![Step::new(Change::Move {
+                                    from: path.clone(),
+                                    to: new_path,
+                                })],
+                            );
+                            Ok(())
                         },
                     );
                 }
             }
+            Key::Char('n') | Key::Char('N') => {
+                let is_dir = key == Key::Char('N');
+                let prompt = if is_dir {
+                    "New directory: "
+                } else {
+                    "New file: "
+                };
+                status_bar.edit(
+                    prompt,
+                    "",
+                    |_, _, _, _| Ok(()),
+                    move |name, this, _, _| {
+                        if name.is_empty() {
+                            bail!("Name can't be empty");
+                        }
+                        let path = this.dir.join(name);
+                        let steps = if is_dir {
+                            create_dirs(&path)?
+                        } else {
+                            OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(&path)
+                                .with_context(|| format!("Failed to create {}", name))?;
+                            vec![Step::new(Change::Create {
+                                path,
+                                is_dir: false,
+                            })]
+                        };
+                        this.select_later(Path::new(name));
+                        this.journal.record(format!("Create {}", name), steps);
+                        Ok(())
+                    },
+                );
+            }
+            Key::Char('u') => self.journal.undo(task_manager)?,
+            Key::Ctrl('r') => self.journal.redo(task_manager)?,
+            Key::Char('i') | Key::Char('I') => {
+                let files = self.targets();
+                let hard = key == Key::Char('I');
+                let kind = if hard { "Hardlink" } else { "Symlink" };
+                let (prompt, text) = match &files[..] {
+                    [] => return Ok(()),
+                    [file] => {
+                        let name = file.file_name().unwrap_or_default().to_string_lossy();
+                        (format!("{} {} as: ", kind, name), name.into_owned())
+                    }
+                    _ => (
+                        format!("{} {} files into: ", kind, files.len()),
+                        ".".to_string(),
+                    ),
+                };
+                status_bar.edit(
+                    prompt,
+                    text,
+                    |_, _, _, _| Ok(()),
+                    move |dest, this, _, _| {
+                        if dest.is_empty() {
+                            bail!("Name can't be empty");
+                        }
+                        let dest = this.dir.join(dest);
+                        let into_dir = files.len() > 1 || dest.is_dir();
+                        let mut steps = vec![];
+                        let res = files.iter().try_for_each(|file| {
+                            let path = if into_dir {
+                                file_ops::target(file, &dest)
+                            } else {
+                                dest.clone()
+                            };
+                            if hard {
+                                fs::hard_link(file, &path)
+                            } else {
+                                symlink(file, &path)
+                            }
+                            .with_context(|| format!("Failed to create {}", path.display()))?;
+                            if steps.is_empty() {
+                                this.select_later(&path);
+                            }
+                            steps.push(Step::new(Change::Link {
+                                target: file.clone(),
+                                path,
+                                hard,
+                            }));
+                            Ok::<_, anyhow::Error>(())
+                        });
+                        this.journal
+                            .record(file_ops::task_name(kind, &files, None), steps);
+                        this.clear_marks();
+                        res
+                    },
+                );
+            }
+            Key::Char('F') | Key::Ctrl('f') => {
+                let gitignore = key == Key::Char('F');
+                let prompt = if gitignore { "Find: " } else { "Find all: " };
+                status_bar.edit(
+                    prompt,
+                    "",
+                    |_, _, _, _| Ok(()),
+                    move |query, this, task_manager, _| {
+                        if query.is_empty() {
+                            bail!("Nothing to find");
+                        }
+                        let search = Search::find_files(
+                            this.dir.clone(),
+                            query,
+                            gitignore,
+                            this.show_hidden,
+                            task_manager,
+                        )?;
+                        this.close_popup();
+                        this.popup = Some(Popup::new(PopupKind::Search, "", vec![]));
+                        this.search = Some(search);
+                        Ok(())
+                    },
+                );
+            }
+            Key::Ctrl('g') => {
+                status_bar.edit(
+                    "Grep: ",
+                    "",
+                    |_, _, _, _| Ok(()),
+                    |query, this, task_manager, _| {
+                        if query.is_empty() {
+                            bail!("Nothing to grep");
+                        }
+                        let search =
+                            Search::grep(this.dir.clone(), query, this.show_hidden, task_manager)?;
+                        this.close_popup();
+                        this.popup = Some(Popup::new(PopupKind::Grep, "", vec![]));
+                        this.search = Some(search);
+                        Ok(())
+                    },
+                );
+            }
+            Key::Char('U') => {
+                self.disk_usage = Some(DiskUsage::scan(self.dir.clone(), task_manager));
+            }
+            Key::Char('R') => {
+                let files = if self.files_marked.is_empty() {
+                    self.files.iter().map(|f| f.path.clone()).collect()
+                } else {
+                    self.files_marked.clone()
+                };
+                let bulk_rename = BulkRename::new(self.dir.clone(), files)?;
+                let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+                shell::run(
+                    self.shell_pid,
+                    &format!("{} {{}} && scd edited {{}}", editor),
+                    &[&bulk_rename.buffer],
+                    true,
+                )?;
+                self.bulk_rename = Some(bulk_rename);
+            }
+            Key::Char('s') | Key::Char('S') | Key::Char('X') | Key::Char('=') => {
+                let files = self.targets();
+                if self.git.is_none() {
+                    status_bar.show_message("Not a git repository");
+                } else if files.is_empty() {
+                    status_bar.show_message("No files selected");
+                } else {
+                    match key {
+                        Key::Char('s') => {
+                            shell::run(self.shell_pid, "git add --", &files, true)?;
+                        }
+                        Key::Char('S') => {
+                            // Without commits there is no HEAD to restore the index from.
+                            let cmd = match &self.git {
+                                Some(git) if !git.has_head => "git rm -r --cached --",
+                                _ => "git restore --staged --",
+                            };
+                            shell::run(self.shell_pid, cmd, &files, true)?;
+                        }
+                        Key::Char('X') => {
+                            // git refuses to restore files it doesn't track.
+                            let (untracked, files): (Vec<PathBuf>, Vec<PathBuf>) =
+                                files.into_iter().partition(|file| self.is_untracked(file));
+                            if files.is_empty() {
+                                bail!("Untracked files have no changes to discard");
+                            }
+                            let skipped = match untracked.len() {
+                                0 => String::new(),
+                                n => format!(", skipping {} untracked", n),
+                            };
+                            status_bar.ask(
+                                format!(
+                                    "Discard changes to {} file(s){}? [y/N]",
+                                    files.len(),
+                                    skipped
+                                ),
+                                move |this, _, _| {
+                                    shell::run(this.shell_pid, "git restore --", &files, true)
+                                },
+                            );
+                        }
+                        _ => {
+                            // Without commits, compare with the empty tree instead of HEAD.
+                            let cmd = match &self.git {
+                                Some(git) if !git.has_head => {
+                                    "git --paginate diff 4b825dc642cb6eb9a060e54bf8d69288fbee4904 --"
+                                }
+                                _ => "git --paginate diff HEAD --",
+                            };
+                            shell::run(self.shell_pid, cmd, &files, true)?
+                        }
+                    }
+                }
+            }
             Key::Char('/') => {
                 status_bar.edit(
                     "/",
                     "",
-                    |filter, this, _| {
+                    |filter, this, _, _| {
                         this.filter = filter.to_owned();
                         this.apply_filter();
                         Ok(())
                     },
-                    |_, this, _| {
+                    |_, this, _, _| {
                         this.filter.clear();
                         this.apply_filter();
                         Ok(())
                     },
                 );
             }
+            Key::Char('f') => {
+                status_bar.edit(
+                    "f",
+                    "",
+                    |prefix, this, _, _| {
+                        this.jump_prefix = prefix.to_owned();
+                        this.jump(true, false);
+                        Ok(())
+                    },
+                    |_, _, _, _| Ok(()),
+                );
+            }
+            Key::Char(';') | Key::Char(',') => {
+                if self.jump_prefix.is_empty() {
+                    status_bar.show_message("No type-ahead prefix, press f to set one");
+                } else if !self.jump(key == Key::Char(';'), true) {
+                    status_bar.show_message(format!("No file starting with {}", self.jump_prefix));
+                }
+            }
             key => self.on_list_key(key)?,
         }
         Ok(())
     }
 
+    fn on_popup_key(&mut self, key: Key, status_bar: &mut StatusBar) -> Result<()> {
+        let popup = self.popup.as_mut().unwrap();
+        match key {
+            Key::Esc => self.close_popup(),
+            // Renames are applied only on an explicit Enter.
+            Key::Char('l') if popup.kind == PopupKind::Rename => {}
+            Key::Char('l') | Key::Char('\n') => self.on_popup_enter()?,
+            Key::Char(' ') | Key::Char('d') if popup.kind == PopupKind::Marked => {
+                if let Some(item) = popup.remove_selected() {
+                    self.unmark(&item.path);
+                }
+            }
+            Key::Char('d') if popup.kind == PopupKind::Trash => {
+                if let Some(item) = popup.selected() {
+                    let file = item.path.clone();
+                    status_bar.ask(
+                        format!("Permanently delete {}? [y/N]", item.label),
+                        move |this, task_manager, _| {
+                            if let Some(popup) = &mut this.popup {
+                                popup.remove_selected();
+                            }
+                            trash::spawn_remove(task_manager, vec![file.clone()]);
+                            Ok(())
+                        },
+                    );
+                }
+            }
+            Key::Char('E') if popup.kind == PopupKind::Trash => {
+                // Empty the whole trash, not just the entries left by the filter.
+                let files: Vec<PathBuf> = trash::list()?.into_iter().map(|i| i.file).collect();
+                if !files.is_empty() {
+                    status_bar.ask(
+                        format!(
+                            "Permanently delete {} file(s) in the trash? [y/N]",
+                            files.len()
+                        ),
+                        move |this, task_manager, _| {
+                            this.close_popup();
+                            trash::spawn_remove(task_manager, files.clone());
+                            Ok(())
+                        },
+                    );
+                }
+            }
+            Key::Char('/') => {
+                status_bar.edit(
+                    "/",
+                    "",
+                    |filter, this, _, _| {
+                        if let Some(popup) = &mut this.popup {
+                            popup.filter = filter.to_owned();
+                            popup.apply_filter();
+                        }
+                        Ok(())
+                    },
+                    |_, this, _, _| this.on_popup_enter(),
+                );
+            }
+            key => popup.on_list_key(key)?,
+        }
+        Ok(())
+    }
+
+    fn close_popup(&mut self) {
+        self.popup = None;
+        if let Some(search) = self.search.take() {
+            search.cancel();
+        }
+    }
+
+    fn on_popup_enter(&mut self) -> Result<()> {
+        let popup = match self.popup.take() {
+            Some(popup) => popup,
+            None => return Ok(()),
+        };
+        self.close_popup();
+        if popup.kind == PopupKind::Rename && popup.selected().is_none() {
+            self.bulk_rename = None;
+            bail!("No renames selected, nothing renamed");
+        }
+        if let Some(item) = popup.selected() {
+            match popup.kind {
+                PopupKind::History | PopupKind::Bookmarks => {
+                    self.cd_with_shell(item.path.clone())?
+                }
+                PopupKind::Rename => {
+                    if let Some(bulk_rename) = self.bulk_rename.take() {
+                        let steps = bulk_rename::plan(bulk_rename.read()?);
+                        bulk_rename::apply(&steps, &self.journal)?;
+                        self.clear_marks();
+                    }
+                }
+                PopupKind::Grep => self.open(vec![item.path.clone()], item.line)?,
+                PopupKind::Trash => {
+                    let path = trash::restore(&item.path)?;
+                    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+                        self.cd_with_shell(dir.to_owned())?;
+                        self.select_file(name);
+                    }
+                }
+                PopupKind::Marked | PopupKind::Search => {
+                    if let (Some(dir), Some(name)) = (item.path.parent(), item.path.file_name()) {
+                        self.cd_with_shell(dir.to_owned())?;
+                        self.select_file(name);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn draw(&mut self, frame: &mut Frame<impl Backend>, area: Rect) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
             .split(area);
 
-        frame.render_widget(
-            Paragraph::new(
-                [Text::styled(
-                    self.dir.to_str().unwrap(),
-                    Style::default().modifier(Modifier::UNDERLINED),
-                )]
-                .iter(),
-            ),
-            chunks[0],
-        );
+        let mut header = vec![Text::styled(
+            self.dir.to_string_lossy(),
+            Style::default().modifier(Modifier::UNDERLINED),
+        )];
+        if let Some(branch) = self.git.as_ref().and_then(|g| g.branch.as_ref()) {
+            header.push(Text::styled(
+                format!(" \u{e0a0} {}", branch),
+                Style::default().fg(Color::LightMagenta),
+            ));
+        }
+        frame.render_widget(Paragraph::new(header.iter()), chunks[0]);
 
-        let items = self
-            .files
-            .iter()
-            .map(|file| {
-                let color = if file.metadata.is_dir() {
-                    Color::Blue
-                } else if file.metadata.permissions().mode() & 0o1 != 0 {
-                    Color::Green
-                } else {
-                    Color::White
-                };
-                let is_selected = if self.files_marked.contains(&file.path) {
-                    "+"
-                } else {
-                    " "
-                };
-                let icon = ""; //self.icons.get(file);
-                let suffix = if file.metadata.is_dir() { "/" } else { "" };
-                Text::styled(
-                    format!("{}{} {}{}", is_selected, icon, file.name, suffix),
-                    Style::default().fg(color),
-                )
-            })
-            .collect::<Vec<_>>()
-            .into_iter();
-        let list =
-            List::new(items).highlight_style(Style::default().fg(Color::Black).bg(Color::Blue));
-        frame.render_stateful_widget(list, chunks[1], &mut self.list_state);
-    }
-}
+        let height = chunks[1].height as usize;
+        // Make sure the list show the selected item
+        self.offset = match self.list_state.selected() {
+            Some(selected) if selected >= self.offset + height => selected + 1 - height,
+            Some(selected) if selected < self.offset => selected,
+            Some(_) => self.offset,
+            None => 0,
+        };
 
-fn load_open_methods() -> Result<HashMap<String, String>> {
-    let config = &env::var("HOME")?;
-    let config = Path::new(&config);
-    let config = config.join(".config/scd/open.yml");
-    let mut res = HashMap::new();
-    match fs::read_to_string(config) {
-        Ok(buf) => {
-            let raw: HashMap<String, String> = serde_yaml::from_str(&buf)?;
-            for (exts, cmd) in raw {
-                for ext in exts.split(',').map(str::trim) {
-                    res.insert(ext.to_string(), cmd.clone());
+        for (i, file) in self.files.iter().enumerate().skip(self.offset).take(height) {
+            let is_selected = if self.files_marked.contains(&file.path) {
+                "+"
+            } else {
+                " "
+            };
+            let icon = self.icons.get(file);
+            let suffix = if file.metadata.is_dir() { "/" } else { "" };
+
+            let highlight = self.list_state.selected() == Some(i);
+            let highlight_style = Style::default().fg(Color::Black).bg(Color::Blue);
+            let style = |style| if highlight { highlight_style } else { style };
+            let mut texts = vec![Text::styled(is_selected, style(Style::default()))];
+            if let Some(git) = &self.git {
+                texts.push(match git.get(&file.name) {
+                    Some(status) => {
+                        Text::styled(status.symbol(), style(Style::default().fg(status.color())))
+                    }
+                    None => Text::styled(" ", style(Style::default())),
+                });
+            }
+            texts.push(Text::styled(
+                format!("{} {}{}", icon, file.display_name(), suffix),
+                style(self.theme.style(file)),
+            ));
+            if let Some(symlink) = &file.symlink {
+                texts.push(Text::styled(
+                    format!(" -> {}", symlink.target.display()),
+                    style(self.theme.link_target_style(symlink)),
+                ));
+            }
+
+            let row = Rect {
+                y: chunks[1].y + (i - self.offset) as u16,
+                height: 1,
+                ..chunks[1]
+            };
+            if highlight {
+                frame.render_widget(
+                    Paragraph::new([Text::raw("")].iter()).style(highlight_style),
+                    row,
+                );
+            }
+            frame.render_widget(Paragraph::new(texts.iter()), row);
+            if file.symlink.is_none() && file.metadata.is_dir() {
+                if let Some(size) = self.size_of(file) {
+                    let size = [Text::styled(
+                        format_size(size),
+                        style(Style::default().fg(Color::DarkGray)),
+                    )];
+                    let style = if highlight {
+                        highlight_style
+                    } else {
+                        Style::default()
+                    };
+                    frame.render_widget(
+                        Paragraph::new(size.iter())
+                            .style(style)
+                            .alignment(Alignment::Right),
+                        row,
+                    );
                 }
             }
         }
-        Err(e) => {
-            if e.kind() != io::ErrorKind::NotFound {
-                return Err(e.into());
+
+        if let Some(disk_usage) = &mut self.disk_usage {
+            disk_usage.draw(frame, chunks[1]);
+        }
+        if let Some(permissions) = &self.permissions {
+            permissions.draw(frame, chunks[1]);
+        }
+        if let Some(popup) = &mut self.popup {
+            if let Some(search) = &self.search {
+                search.poll(popup);
             }
+            popup.draw(frame, chunks[1]);
         }
     }
-    Ok(res)
+}
+
+/// Create `dir` and its missing parents, returning the steps to undo it.
+fn create_dirs(dir: &Path) -> Result<Vec<Step>> {
+    let mut missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|d| fs::symlink_metadata(d).is_err())
+        .collect();
+    if missing.is_empty() {
+        bail!("{} already exists", dir.display());
+    }
+    missing.reverse();
+    let mut steps = vec![];
+    for dir in missing {
+        fs::create_dir(dir)?;
+        steps.push(Step::new(Change::Create {
+            path: dir.to_owned(),
+            is_dir: true,
+        }));
+    }
+    Ok(steps)
+}
+
+fn load_open_methods() -> Result<HashMap<String, String>> {
+    let raw: HashMap<String, String> = config::load("open.yml")?;
+    Ok(config::split_keys(raw))
 }
 
 impl<W> ListExt for FileManager<W>
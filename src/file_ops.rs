@@ -0,0 +1,448 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::{TimeSpec, TimeValLike};
+
+use crate::dir_sizes::{size, walk};
+use crate::journal::{Change, Journal, Step};
+use crate::status_bar::{format_size, StatusBar};
+use crate::task_manager::{Progress, TaskManager, REFRESH_INTERVAL};
+use crate::trash;
+
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Files and bytes done so far, out of the totals measured before starting.
+pub struct Counter<'a> {
+    progress: &'a Progress,
+    files: u64,
+    bytes: u64,
+    total_files: u64,
+    total_bytes: u64,
+    last_update: Instant,
+}
+
+impl<'a> Counter<'a> {
+    pub fn new(progress: &'a Progress, paths: &[PathBuf]) -> Counter<'a> {
+        let mut counter = Counter {
+            progress,
+            files: 0,
+            bytes: 0,
+            total_files: 0,
+            total_bytes: 0,
+            last_update: Instant::now(),
+        };
+        for path in paths {
+            let (files, bytes) = measure(path);
+            counter.total_files += files;
+            counter.total_bytes += bytes;
+        }
+        counter.update();
+        counter
+    }
+
+    fn update(&mut self) {
+        self.progress.update(format!(
+            "{}/{} files, {}/{}",
+            self.files,
+            self.total_files,
+            format_size(self.bytes),
+            format_size(self.total_bytes)
+        ));
+        self.last_update = Instant::now();
+    }
+
+    fn add(&mut self, files: u64, bytes: u64) -> Result<()> {
+        self.progress.check()?;
+        self.files += files;
+        self.bytes += bytes;
+        if self.last_update.elapsed() >= REFRESH_INTERVAL {
+            self.update();
+        }
+        Ok(())
+    }
+}
+
+/// Number of files and bytes under `path`. Symlinks are not followed.
+fn measure(path: &Path) -> (u64, u64) {
+    let res = walk(path, &mut |_, metadata, children: Vec<(u64, u64)>| {
+        let files = 1 + children.iter().map(|c| c.0).sum::<u64>();
+        Ok((files, size(metadata, children.iter().map(|c| c.1))))
+    });
+    res.ok().flatten().unwrap_or((0, 0))
+}
+
+/// Where `file` goes when pasted into `dest`.
+pub fn target(file: &Path, dest: &Path) -> PathBuf {
+    dest.join(file.file_name().unwrap_or_default())
+}
+
+fn set_times(path: &Path, metadata: &fs::Metadata) -> Result<()> {
+    let time = |secs: i64, nsecs: i64| TimeSpec::nanoseconds(secs * 1_000_000_000 + nsecs);
+    let atime = time(metadata.atime(), metadata.atime_nsec());
+    let mtime = time(metadata.mtime(), metadata.mtime_nsec());
+    utimensat(None, path, &atime, &mtime, UtimensatFlags::NoFollowSymlink)?;
+    Ok(())
+}
+
+fn copy_file(src: &Path, dest: &Path, counter: &mut Counter) -> Result<()> {
+    let mut reader = File::open(src)?;
+    let mut writer = File::create(dest)?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..n])?;
+        counter.add(0, n as u64)?;
+    }
+    Ok(())
+}
+
+/// Copy `src` to `dest` recursively, keeping modes, timestamps and symlinks.
+pub fn copy_tree(src: &Path, dest: &Path, counter: &mut Counter) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dest)?;
+    } else if file_type.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dest.join(entry.file_name()), counter)?;
+        }
+        // Set after the contents are written, in case the mode doesn't allow writing.
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else if file_type.is_file() {
+        if let Err(e) = copy_file(src, dest, counter) {
+            let _ = fs::remove_file(dest);
+            return Err(e);
+        }
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else {
+        bail!("Can't copy special file {}", src.display());
+    }
+    set_times(dest, &metadata)?;
+    counter.add(1, 0)
+}
+
+/// Remove `path` recursively. Symlinks are removed, not followed.
+fn remove_tree(path: &Path, counter: &mut Counter) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            remove_tree(&entry?.path(), counter)?;
+        }
+        fs::remove_dir(path)?;
+        counter.add(1, 0)
+    } else {
+        fs::remove_file(path)?;
+        counter.add(1, metadata.len())
+    }
+}
+
+/// Remove a file, or a directory with everything in it.
+fn remove_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Rename `file` to `target`, or copy and remove it when `target` is on another filesystem.
+pub fn move_tree(file: &Path, target: &Path, counter: &mut Counter) -> Result<()> {
+    match fs::rename(file, target) {
+        Ok(()) => {
+            let (files, bytes) = measure(target);
+            counter.add(files, bytes)
+        }
+        Err(e) if e.raw_os_error() == Some(Errno::EXDEV as i32) => {
+            copy_tree(file, target, counter)?;
+            Ok(remove_path(file)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The first of `path` with `suffix(1)`, `suffix(2)`, ... that doesn't exist.
+fn free_name<S: AsRef<OsStr>>(path: &Path, suffix: impl Fn(u32) -> S) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            name.push(suffix(n));
+            path.with_file_name(name)
+        })
+        .find(|p| fs::symlink_metadata(p).is_err())
+        .unwrap()
+}
+
+/// `foo.tar.gz` becomes `foo_1.tar.gz`, `foo_2.tar.gz`, ...
+fn renamed(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().as_bytes();
+    // The leading dot of hidden files doesn't start an extension.
+    let split = name
+        .iter()
+        .skip(1)
+        .position(|&b| b == b'.')
+        .map_or(name.len(), |i| i + 1);
+    let (stem, extension) = name.split_at(split);
+    let with_stem = path.with_file_name(OsStr::from_bytes(stem));
+    let extension = OsStr::from_bytes(extension).to_owned();
+    free_name(&with_stem, |n| {
+        let mut suffix = OsString::from(format!("_{}", n));
+        suffix.push(&extension);
+        suffix
+    })
+}
+
+pub fn task_name(verb: &str, files: &[PathBuf], dest: Option<&Path>) -> String {
+    let what = match files {
+        [file] => file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{} files", files.len()),
+    };
+    match dest {
+        Some(dest) => format!("{} {} to {}", verb, what, dest.display()),
+        None => format!("{} {}", verb, what),
+    }
+}
+
+/// What to do when a pasted file's name is taken.
+#[derive(Clone, Copy, PartialEq)]
+enum Resolution {
+    Overwrite,
+    Skip,
+    /// Paste under a name with a number added.
+    Rename,
+    /// Move the existing file to a numbered backup like `cp --backup=numbered`.
+    Backup,
+}
+
+impl Resolution {
+    const CHOICES: [(char, &'static str); 4] = [
+        ('o', "verwrite"),
+        ('s', "kip"),
+        ('r', "ename"),
+        ('b', "ackup"),
+    ];
+
+    fn from_key(key: char) -> Option<Resolution> {
+        match key.to_ascii_lowercase() {
+            'o' => Some(Resolution::Overwrite),
+            's' => Some(Resolution::Skip),
+            'r' => Some(Resolution::Rename),
+            'b' => Some(Resolution::Backup),
+            _ => None,
+        }
+    }
+}
+
+/// Make room for pasting `file` into `dest`, returning where it goes, or `None` to skip it.
+///
+/// Existing files are trashed or renamed to make room, recorded in `steps`.
+fn prepare(
+    file: &Path,
+    dest: &Path,
+    resolution: Option<Resolution>,
+    is_move: bool,
+    steps: &mut Vec<Step>,
+) -> Result<Option<PathBuf>> {
+    let target = target(file, dest);
+    let resolution = match resolution {
+        // The file it conflicted with in this paste may have been skipped.
+        Some(resolution) if fs::symlink_metadata(&target).is_ok() => resolution,
+        _ => return Ok(Some(target)),
+    };
+    // Pasting a file onto itself, or onto a directory containing it.
+    if file.starts_with(&target) {
+        return match resolution {
+            Resolution::Rename if !is_move => Ok(Some(renamed(&target))),
+            Resolution::Skip | Resolution::Rename => Ok(None),
+            _ if file == target => Ok(None),
+            _ => bail!("{} contains {}", target.display(), file.display()),
+        };
+    }
+    let change = match resolution {
+        Resolution::Overwrite => Change::Trash {
+            file: trash::trash(&target)?,
+            original: target.clone(),
+        },
+        Resolution::Skip => return Ok(None),
+        Resolution::Rename => return Ok(Some(renamed(&target))),
+        Resolution::Backup => {
+            let backup = free_name(&target, |n| format!(".~{}~", n));
+            fs::rename(&target, &backup)?;
+            Change::Move {
+                from: target.clone(),
+                to: backup,
+            }
+        }
+    };
+    steps.push(Step::new(change));
+    Ok(Some(target))
+}
+
+/// Files being copied or moved into `dest`, asking how to resolve name conflicts first.
+#[derive(Clone)]
+pub struct Paste {
+    dest: PathBuf,
+    is_move: bool,
+    /// Files to paste, with how to resolve their conflicts if they have one.
+    resolved: Vec<(PathBuf, Option<Resolution>)>,
+    conflicts: Vec<PathBuf>,
+    journal: Journal,
+}
+
+impl Paste {
+    pub fn new(
+        files: Vec<PathBuf>,
+        dest: PathBuf,
+        is_move: bool,
+        journal: Journal,
+    ) -> Result<Paste> {
+        let mut paste = Paste {
+            dest,
+            is_move,
+            resolved: vec![],
+            conflicts: vec![],
+            journal,
+        };
+        // Files with the same name from different directories conflict with each other.
+        let mut claimed = HashSet::new();
+        for file in files {
+            let target = target(&file, &paste.dest);
+            if paste.dest.starts_with(&file) && target != file {
+                bail!("Can't paste {} into itself", file.display());
+            }
+            if fs::symlink_metadata(&target).is_ok() || !claimed.insert(target) {
+                paste.conflicts.push(file);
+            } else {
+                paste.resolved.push((file, None));
+            }
+        }
+        Ok(paste)
+    }
+
+    /// Ask about the next conflict, or start pasting once all are resolved.
+    /// Choices given in upper case apply to all remaining conflicts.
+    /// Returns whether pasting started right away; when it starts after the conflicts are
+    /// answered, the marked files are unmarked then, so cancelling the prompt keeps them.
+    pub fn resolve(self, status_bar: &mut StatusBar, task_manager: &mut TaskManager) -> bool {
+        let file = match self.conflicts.first() {
+            Some(file) => file,
+            None => return self.spawn(status_bar, task_manager),
+        };
+        let prompt = format!(
+            "{} exists{}:",
+            file.file_name().unwrap_or_default().to_string_lossy(),
+            match self.conflicts.len() {
+                1 => String::new(),
+                n => format!(" ({} conflicts, shift for all)", n),
+            }
+        );
+        status_bar.select(
+            prompt,
+            &Resolution::CHOICES,
+            move |key, this, task_manager, status_bar| {
+                let mut paste = self.clone();
+                let resolution = Resolution::from_key(key);
+                let count = if key.is_ascii_uppercase() {
+                    paste.conflicts.len()
+                } else {
+                    1
+                };
+                for file in paste.conflicts.drain(..count) {
+                    paste.resolved.push((file, resolution));
+                }
+                if paste.resolve(status_bar, task_manager) {
+                    this.clear_marks();
+                }
+                Ok(())
+            },
+        );
+        false
+    }
+
+    fn spawn(self, status_bar: &mut StatusBar, task_manager: &mut TaskManager) -> bool {
+        let Paste {
+            dest,
+            is_move,
+            mut resolved,
+            journal,
+            ..
+        } = self;
+        resolved.retain(|(_, resolution)| resolution != &Some(Resolution::Skip));
+        if resolved.is_empty() {
+            status_bar.show_message("Nothing to paste");
+            return false;
+        }
+        let files: Vec<PathBuf> = resolved.iter().map(|(file, _)| file.clone()).collect();
+        let verb = if is_move { "Move" } else { "Copy" };
+        let name = task_name(verb, &files, Some(&dest));
+        task_manager.spawn(name.clone(), move |progress| {
+            let mut counter = Counter::new(progress, &files);
+            let mut steps = vec![];
+            // Whatever was pasted before an error can still be undone.
+            let res = paste(&resolved, &dest, is_move, &mut counter, &mut steps);
+            journal.record(name, steps);
+            res
+        });
+        true
+    }
+}
+
+fn paste(
+    resolved: &[(PathBuf, Option<Resolution>)],
+    dest: &Path,
+    is_move: bool,
+    counter: &mut Counter,
+    steps: &mut Vec<Step>,
+) -> Result<()> {
+    for (file, resolution) in resolved {
+        let target = match prepare(file, dest, *resolution, is_move, steps)? {
+            Some(target) => target,
+            None => continue,
+        };
+        let res = if is_move {
+            move_tree(file, &target, counter)
+        } else {
+            copy_tree(file, &target, counter)
+        };
+        // Whatever a failed or cancelled paste left at the target can be undone too.
+        if res.is_ok() || fs::symlink_metadata(&target).is_ok() {
+            let from = file.clone();
+            let change = if is_move && fs::symlink_metadata(file).is_err() {
+                Change::Move { from, to: target }
+            } else {
+                Change::Copy { from, to: target }
+            };
+            steps.push(Step::new(change));
+        }
+        res?;
+    }
+    Ok(())
+}
+
+/// Delete `files` recursively as a task.
+pub fn spawn_delete(task_manager: &mut TaskManager, files: Vec<PathBuf>) {
+    task_manager.spawn(task_name("Delete", &files, None), move |progress| {
+        let mut counter = Counter::new(progress, &files);
+        for file in &files {
+            remove_tree(file, &mut counter)?;
+        }
+        Ok(())
+    });
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use crossbeam_channel::{self as channel, Receiver, Sender};
+use tui::style::Color;
+
+/// The git state of a file, ordered by how important it is to show it.
+///
+/// A directory takes the most important state of all its descendants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileStatus {
+    Ignored,
+    Untracked,
+    Staged,
+    Modified,
+    Conflicted,
+}
+
+impl FileStatus {
+    /// Parse the `XY` status code of `git status --porcelain`.
+    fn parse(x: u8, y: u8) -> FileStatus {
+        match (x, y) {
+            (b'!', b'!') => FileStatus::Ignored,
+            (b'?', b'?') => FileStatus::Untracked,
+            (b'U', _) | (_, b'U') | (b'A', b'A') | (b'D', b'D') => FileStatus::Conflicted,
+            (_, b' ') => FileStatus::Staged,
+            _ => FileStatus::Modified,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            FileStatus::Ignored => "!",
+            FileStatus::Untracked => "?",
+            FileStatus::Staged => "+",
+            FileStatus::Modified => "~",
+            FileStatus::Conflicted => "×",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            FileStatus::Ignored => Color::DarkGray,
+            FileStatus::Untracked => Color::LightMagenta,
+            FileStatus::Staged => Color::LightGreen,
+            FileStatus::Modified => Color::LightYellow,
+            FileStatus::Conflicted => Color::LightRed,
+        }
+    }
+}
+
+/// `git status` of the entries of one directory.
+#[derive(Debug, Default)]
+pub struct GitStatus {
+    pub branch: Option<String>,
+
+    /// Whether the branch has commits, so `HEAD` can be compared with.
+    pub has_head: bool,
+
+    /// Status of the direct children, rolled up from their descendants.
+    children: HashMap<OsString, FileStatus>,
+
+    /// Status inherited from an untracked or ignored ancestor directory.
+    inherited: Option<FileStatus>,
+}
+
+impl GitStatus {
+    /// Run `git status` in `dir`, returns `None` if it's not inside a work tree.
+    pub fn load(dir: &Path) -> Option<GitStatus> {
+        let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+        let root = Path::new(OsStr::from_bytes(root.strip_suffix(b"\n")?));
+        // The root is always a real path, while `dir` may contain symlinks.
+        let dir = dir.canonicalize().ok()?;
+        let output = git(
+            &dir,
+            &[
+                "status",
+                "--porcelain",
+                "--branch",
+                "--ignored",
+                "-z",
+                "--",
+                ".",
+            ],
+        )?;
+        let mut status = GitStatus::default();
+        let mut entries = output.split(|&b| b == 0);
+        while let Some(entry) = entries.next() {
+            if let Some(branch) = entry.strip_prefix(b"## ") {
+                let branch = String::from_utf8_lossy(branch);
+                status.has_head = !branch.starts_with("No commits yet on ");
+                status.branch = parse_branch(&branch);
+                continue;
+            }
+            if entry.len() < 4 {
+                continue;
+            }
+            // Renames and copies are followed by the original path.
+            if entry[0] == b'R' || entry[0] == b'C' {
+                entries.next();
+            }
+            let path = root.join(OsStr::from_bytes(&entry[3..]));
+            status.add(&dir, &path, FileStatus::parse(entry[0], entry[1]));
+        }
+        Some(status)
+    }
+
+    fn add(&mut self, dir: &Path, path: &Path, file_status: FileStatus) {
+        match path.strip_prefix(dir) {
+            Ok(relative) => match relative.components().next() {
+                Some(Component::Normal(name)) => {
+                    let entry = self.children.entry(name.to_owned()).or_insert(file_status);
+                    *entry = (*entry).max(file_status);
+                }
+                // `dir` itself is untracked or ignored.
+                _ => self.inherited = self.inherited.max(Some(file_status)),
+            },
+            Err(_) if dir.starts_with(path) => {
+                self.inherited = self.inherited.max(Some(file_status))
+            }
+            Err(_) => {}
+        }
+    }
+
+    pub fn get(&self, name: &OsStr) -> Option<FileStatus> {
+        self.children.get(name).copied().or(self.inherited)
+    }
+}
+
+/// A finished `git status` of a directory.
+pub struct LoadedStatus {
+    pub dir: PathBuf,
+    pub status: Option<GitStatus>,
+}
+
+/// Runs `git status` on a background thread.
+pub struct GitLoader {
+    jobs: Sender<PathBuf>,
+}
+
+impl GitLoader {
+    pub fn new() -> (GitLoader, Receiver<LoadedStatus>) {
+        let (jobs, job_rx) = channel::unbounded::<PathBuf>();
+        let (tx, rx) = channel::unbounded();
+        thread::spawn(move || {
+            while let Ok(mut dir) = job_rx.recv() {
+                // Requests made while git was running only need the last one answered.
+                while let Ok(newer) = job_rx.try_recv() {
+                    dir = newer;
+                }
+                let status = GitStatus::load(&dir);
+                if tx.send(LoadedStatus { dir, status }).is_err() {
+                    break;
+                }
+            }
+        });
+        (GitLoader { jobs }, rx)
+    }
+
+    pub fn request(&self, dir: &Path) {
+        let _ = self.jobs.send(dir.to_owned());
+    }
+}
+
+fn parse_branch(header: &str) -> Option<String> {
+    if let Some(branch) = header.strip_prefix("No commits yet on ") {
+        return Some(branch.to_owned());
+    }
+    if header.starts_with("HEAD (no branch)") {
+        return Some("HEAD".to_owned());
+    }
+    header
+        .split("...")
+        .next()
+        .map(|b| b.split(' ').next().unwrap_or(b).to_owned())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(output.stdout)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::config;
+use crate::file_manager::FileInfo;
+
+const DIRECTORY: &str = "\u{f115}";
+const FILE: &str = "\u{f15b}";
+const SYMLINK: &str = "\u{f0c1}";
+const EXECUTABLE: &str = "\u{f489}";
+
+const DIRECTORIES: &[(&str, &str)] = &[
+    (".git", "\u{f1d3}"),
+    (".github", "\u{f408}"),
+    (".config", "\u{e5fc}"),
+    ("node_modules", "\u{e718}"),
+    ("Desktop", "\u{f108}"),
+    ("Documents", "\u{f15c}"),
+    ("Downloads", "\u{f019}"),
+    ("Music", "\u{f001}"),
+    ("Pictures", "\u{f03e}"),
+    ("Videos", "\u{f03d}"),
+];
+
+const NAMES: &[(&str, &str)] = &[
+    (".gitignore", "\u{f1d3}"),
+    (".gitattributes", "\u{f1d3}"),
+    (".gitmodules", "\u{f1d3}"),
+    (".bashrc", "\u{f489}"),
+    (".zshrc", "\u{f489}"),
+    ("Cargo.toml", "\u{e7a8}"),
+    ("Cargo.lock", "\u{e7a8}"),
+    ("Dockerfile", "\u{f308}"),
+    ("LICENSE", "\u{f02d}"),
+    ("Makefile", "\u{f489}"),
+    ("package.json", "\u{e71e}"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "\u{e7a8}"),
+    ("py", "\u{e606}"),
+    ("go", "\u{e626}"),
+    ("js", "\u{e74e}"),
+    ("ts", "\u{e628}"),
+    ("c", "\u{e61e}"),
+    ("h", "\u{f0fd}"),
+    ("cc", "\u{e61d}"),
+    ("cpp", "\u{e61d}"),
+    ("hpp", "\u{f0fd}"),
+    ("java", "\u{e204}"),
+    ("rb", "\u{e21e}"),
+    ("lua", "\u{e620}"),
+    ("vim", "\u{e62b}"),
+    ("html", "\u{f13b}"),
+    ("css", "\u{e749}"),
+    ("json", "\u{e60b}"),
+    ("toml", "\u{e615}"),
+    ("yml", "\u{f481}"),
+    ("yaml", "\u{f481}"),
+    ("md", "\u{f48a}"),
+    ("txt", "\u{f15c}"),
+    ("log", "\u{f18d}"),
+    ("lock", "\u{f023}"),
+    ("sh", "\u{f489}"),
+    ("bash", "\u{f489}"),
+    ("zsh", "\u{f489}"),
+    ("fish", "\u{f489}"),
+    ("pdf", "\u{f1c1}"),
+    ("doc", "\u{f1c2}"),
+    ("docx", "\u{f1c2}"),
+    ("xls", "\u{f1c3}"),
+    ("xlsx", "\u{f1c3}"),
+    ("ppt", "\u{f1c4}"),
+    ("pptx", "\u{f1c4}"),
+    ("db", "\u{f1c0}"),
+    ("sqlite", "\u{f1c0}"),
+    ("png", "\u{f1c5}"),
+    ("jpg", "\u{f1c5}"),
+    ("jpeg", "\u{f1c5}"),
+    ("gif", "\u{f1c5}"),
+    ("bmp", "\u{f1c5}"),
+    ("svg", "\u{f1c5}"),
+    ("webp", "\u{f1c5}"),
+    ("mp3", "\u{f001}"),
+    ("flac", "\u{f001}"),
+    ("ogg", "\u{f001}"),
+    ("wav", "\u{f001}"),
+    ("mp4", "\u{f03d}"),
+    ("mkv", "\u{f03d}"),
+    ("webm", "\u{f03d}"),
+    ("avi", "\u{f03d}"),
+    ("mov", "\u{f03d}"),
+    ("zip", "\u{f410}"),
+    ("tar", "\u{f410}"),
+    ("gz", "\u{f410}"),
+    ("xz", "\u{f410}"),
+    ("bz2", "\u{f410}"),
+    ("zst", "\u{f410}"),
+    ("7z", "\u{f410}"),
+    ("rar", "\u{f410}"),
+    ("deb", "\u{e77d}"),
+    ("rpm", "\u{e7bb}"),
+    ("iso", "\u{e271}"),
+];
+
+/// `~/.config/scd/icons.yml`
+///
+/// ```yaml
+/// nerd_font: true
+/// directories:
+///   src: ""
+/// names:
+///   justfile: ""
+/// extensions:
+///   rs, ron: ""
+/// ```
+#[derive(Deserialize)]
+#[serde(default)]
+struct IconsConfig {
+    /// Set to `false` for terminals without a Nerd Font.
+    nerd_font: bool,
+    directories: HashMap<String, String>,
+    names: HashMap<String, String>,
+    extensions: HashMap<String, String>,
+}
+
+impl Default for IconsConfig {
+    fn default() -> Self {
+        Self {
+            nerd_font: true,
+            directories: HashMap::new(),
+            names: HashMap::new(),
+            extensions: HashMap::new(),
+        }
+    }
+}
+
+pub struct Icons {
+    nerd_font: bool,
+    directories: HashMap<String, String>,
+    names: HashMap<String, String>,
+    extensions: HashMap<String, String>,
+}
+
+impl Icons {
+    pub fn load() -> Result<Icons> {
+        let config: IconsConfig = config::load("icons.yml")?;
+        let table = |builtin: &[(&str, &str)], overrides| {
+            let mut res: HashMap<String, String> = builtin
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            res.extend(config::split_keys(overrides));
+            res
+        };
+        // Extensions are looked up in lowercase.
+        let extensions = config
+            .extensions
+            .into_iter()
+            .map(|(keys, icon)| (keys.to_lowercase(), icon))
+            .collect();
+        Ok(Icons {
+            nerd_font: config.nerd_font,
+            directories: table(DIRECTORIES, config.directories),
+            names: table(NAMES, config.names),
+            extensions: table(EXTENSIONS, extensions),
+        })
+    }
+
+    pub fn get(&self, file: &FileInfo) -> &str {
+        let is_executable = file.metadata.mode() & 0o111 != 0;
+        if !self.nerd_font {
+            return if file.symlink.is_some() {
+                "l"
+            } else if file.metadata.is_dir() {
+                "d"
+            } else if is_executable {
+                "x"
+            } else {
+                "-"
+            };
+        }
+
+        if file.symlink.is_some() {
+            return SYMLINK;
+        }
+        if file.metadata.is_dir() {
+            return file
+                .name
+                .to_str()
+                .and_then(|name| self.directories.get(name))
+                .map(String::as_str)
+                .unwrap_or(DIRECTORY);
+        }
+        if let Some(icon) = file.name.to_str().and_then(|name| self.names.get(name)) {
+            return icon;
+        }
+        let by_extension = file
+            .extension
+            .as_ref()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.extensions.get(&ext.to_lowercase()));
+        match by_extension {
+            Some(icon) => icon,
+            None if is_executable => EXECUTABLE,
+            None => FILE,
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::file_ops::{copy_tree, move_tree, Counter};
+use crate::task_manager::{Progress, TaskManager};
+use crate::trash;
+
+/// Identity of a file, to tell whether it was replaced after a change.
+#[derive(Clone, Copy, PartialEq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    fs::symlink_metadata(path).ok().map(|metadata| Stamp {
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+    })
+}
+
+/// A change made to the file system.
+pub enum Change {
+    /// `from` was renamed or moved to `to`.
+    Move { from: PathBuf, to: PathBuf },
+    /// `to` was copied from `from`.
+    Copy { from: PathBuf, to: PathBuf },
+    /// An empty file or directory was created.
+    Create { path: PathBuf, is_dir: bool },
+    /// `original` was moved to the trash as `file`.
+    Trash { original: PathBuf, file: PathBuf },
+    /// A symlink or hard link to `target` was made at `path`.
+    Link {
+        target: PathBuf,
+        path: PathBuf,
+        hard: bool,
+    },
+}
+
+impl Change {
+    /// The file a change takes away and the path it puts a file at, when undoing or redoing it.
+    fn paths(&self, undo: bool) -> (Option<&Path>, Option<&Path>) {
+        match (self, undo) {
+            (Change::Move { from, to }, true) => (Some(to), Some(from)),
+            (Change::Move { from, to }, false) => (Some(from), Some(to)),
+            (Change::Copy { to, .. }, true) => (Some(to), None),
+            (Change::Copy { from, to }, false) => (Some(from), Some(to)),
+            (Change::Create { path, .. }, true) => (Some(path), None),
+            (Change::Create { path, .. }, false) => (None, Some(path)),
+            (Change::Trash { original, file }, true) => (Some(file), Some(original)),
+            (Change::Trash { original, .. }, false) => (Some(original), None),
+            (Change::Link { path, .. }, true) => (Some(path), None),
+            // A hard link needs its target, a symlink may dangle.
+            (Change::Link { target, path, hard }, false) => {
+                ((*hard).then_some(target.as_path()), Some(path))
+            }
+        }
+    }
+
+    /// The file left behind by the change, or by undoing it.
+    fn result(&self, undone: bool) -> Option<&Path> {
+        match (self, undone) {
+            (Change::Move { from, .. }, true) => Some(from),
+            (Change::Trash { original, .. }, true) => Some(original),
+            (_, true) => None,
+            (Change::Move { to, .. }, false) | (Change::Copy { to, .. }, false) => Some(to),
+            (Change::Create { path, .. }, false) | (Change::Link { path, .. }, false) => Some(path),
+            (Change::Trash { file, .. }, false) => Some(file),
+        }
+    }
+
+    fn apply(&mut self, undo: bool, counter: &mut Counter) -> Result<()> {
+        match (self, undo) {
+            (Change::Move { from, to }, true) => move_tree(to, from, counter)?,
+            (Change::Move { from, to }, false) => move_tree(from, to, counter)?,
+            // Copies go to the trash, in case they were edited.
+            (Change::Copy { to, .. }, true) => {
+                trash::trash(to)?;
+            }
+            (Change::Copy { from, to }, false) => copy_tree(from, to, counter)?,
+            (Change::Create { path, is_dir }, true) => {
+                if *is_dir {
+                    fs::remove_dir(path)?
+                } else {
+                    fs::remove_file(path)?
+                }
+            }
+            (Change::Create { path, is_dir }, false) => {
+                if *is_dir {
+                    fs::create_dir(path)?
+                } else {
+                    OpenOptions::new().write(true).create_new(true).open(path)?;
+                }
+            }
+            (Change::Trash { file, .. }, true) => {
+                trash::restore(file)?;
+            }
+            (Change::Trash { original, file }, false) => *file = trash::trash(original)?,
+            (Change::Link { path, hard, .. }, true) => {
+                // The last link to a file goes to the trash, as its target is gone.
+                if *hard && fs::symlink_metadata(&path)?.nlink() == 1 {
+                    trash::trash(path)?;
+                } else {
+                    fs::remove_file(path)?
+                }
+            }
+            (Change::Link { target, path, hard }, false) => {
+                if *hard {
+                    fs::hard_link(target, path)?
+                } else {
+                    symlink(target, path)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A change, with the identity of the file it left behind.
+pub struct Step {
+    change: Change,
+    stamp: Option<Stamp>,
+}
+
+impl Step {
+    /// Record a change that was just made.
+    pub fn new(change: Change) -> Step {
+        let stamp = change.result(false).and_then(stamp);
+        Step { change, stamp }
+    }
+}
+
+/// Steps done by one command, undone together.
+struct Operation {
+    name: String,
+    steps: Vec<Step>,
+}
+
+impl Operation {
+    fn steps(&mut self, undo: bool) -> Box<dyn Iterator<Item = &mut Step> + '_> {
+        if undo {
+            Box::new(self.steps.iter_mut().rev())
+        } else {
+            Box::new(self.steps.iter_mut())
+        }
+    }
+
+    /// Make sure every step can be reversed without replacing or losing a file,
+    /// following the files through the earlier steps.
+    fn check(&mut self, undo: bool) -> Result<()> {
+        let mut exists: HashMap<PathBuf, bool> = HashMap::new();
+        let exists_at = |exists: &HashMap<PathBuf, bool>, path: &Path| {
+            exists
+                .get(path)
+                .copied()
+                .unwrap_or_else(|| fs::symlink_metadata(path).is_ok())
+        };
+        for step in self.steps(undo) {
+            let (source, dest) = step.change.paths(undo);
+            if let Some(source) = source {
+                match exists.get(source) {
+                    Some(true) => {}
+                    Some(false) => bail!("{} is gone", source.display()),
+                    None => {
+                        let current = stamp(source)
+                            .with_context(|| format!("{} is gone", source.display()))?;
+                        if let Some(stamp) = step.stamp {
+                            ensure!(current == stamp, "{} was replaced", source.display());
+                        }
+                    }
+                }
+                match step.change {
+                    Change::Create { is_dir: true, .. } => {
+                        let mut entries = fs::read_dir(source)?;
+                        ensure!(
+                            entries.all(|e| e.is_ok_and(|e| exists.get(&e.path()) == Some(&false))),
+                            "{} is not empty",
+                            source.display()
+                        );
+                    }
+                    Change::Create { is_dir: false, .. } => {
+                        ensure!(
+                            fs::symlink_metadata(source)?.len() == 0,
+                            "{} was written to",
+                            source.display()
+                        );
+                    }
+                    _ => {}
+                }
+                let kept = matches!(step.change, Change::Copy { .. } | Change::Link { .. });
+                if undo || !kept {
+                    exists.insert(source.to_owned(), false);
+                }
+            }
+            if let Some(dest) = dest {
+                ensure!(
+                    !exists_at(&exists, dest),
+                    "{} already exists",
+                    dest.display()
+                );
+                // Restoring from the trash creates missing directories.
+                let restored = matches!(step.change, Change::Trash { .. });
+                if let Some(parent) = dest.parent().filter(|_| !restored) {
+                    ensure!(exists_at(&exists, parent), "{} is gone", parent.display());
+                }
+                exists.insert(dest.to_owned(), true);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, undo: bool, progress: &Progress) -> Result<()> {
+        let moved: Vec<PathBuf> = self
+            .steps
+            .iter()
+            .filter(|step| matches!(step.change, Change::Move { .. } | Change::Copy { .. }))
+            .filter_map(|step| step.change.paths(undo).0.map(Path::to_owned))
+            .collect();
+        let mut counter = Counter::new(progress, &moved);
+        for step in self.steps(undo) {
+            progress.check()?;
+            step.change.apply(undo, &mut counter)?;
+            step.stamp = step.change.result(undo).and_then(stamp);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct History {
+    done: Vec<Operation>,
+    undone: Vec<Operation>,
+    /// Whether an undo or redo is running, which must finish before the next one.
+    busy: bool,
+}
+
+/// Renames, moves, copies, creations, links and trashing done from scd, which can be undone.
+///
+/// Cloned handles share the same history, so tasks can record what they did.
+#[derive(Clone, Default)]
+pub struct Journal {
+    history: Arc<Mutex<History>>,
+}
+
+impl Journal {
+    /// Record an operation named `name`, made of `steps` in the order they were done.
+    pub fn record(&self, name: impl Into<String>, steps: Vec<Step>) {
+        if steps.is_empty() {
+            return;
+        }
+        let mut history = self.history.lock().unwrap();
+        history.done.push(Operation {
+            name: name.into(),
+            steps,
+        });
+        history.undone.clear();
+    }
+
+    pub fn undo(&self, task_manager: &mut TaskManager) -> Result<()> {
+        self.travel(true, task_manager)
+    }
+
+    pub fn redo(&self, task_manager: &mut TaskManager) -> Result<()> {
+        self.travel(false, task_manager)
+    }
+
+    /// Undo the last operation, or redo the last undone one, as a task.
+    fn travel(&self, undo: bool, task_manager: &mut TaskManager) -> Result<()> {
+        let verb = if undo { "undo" } else { "redo" };
+        let mut history = self.history.lock().unwrap();
+        ensure!(!history.busy, "Wait for the last {} to finish", verb);
+        let stack = if undo {
+            &mut history.done
+        } else {
+            &mut history.undone
+        };
+        let mut operation = match stack.pop() {
+            Some(operation) => operation,
+            None => bail!("Nothing to {}", verb),
+        };
+        if let Err(e) = operation.check(undo) {
+            let name = operation.name.clone();
+            stack.push(operation);
+            bail!("Can't {} \"{}\": {}", verb, name, e);
+        }
+        history.busy = true;
+
+        let journal = self.clone();
+        let name = if undo { "Undo" } else { "Redo" };
+        let name = format!("{} \"{}\"", name, operation.name);
+        task_manager.spawn(name, move |progress| {
+            let res = operation.apply(undo, progress);
+            let mut history = journal.history.lock().unwrap();
+            history.busy = false;
+            // A failed operation is left half done, and can't be reversed as a whole.
+            if res.is_ok() {
+                if undo {
+                    history.undone.push(operation);
+                } else {
+                    history.done.push(operation);
+                }
+            }
+            res
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(steps: Vec<Step>) -> Operation {
+        Operation {
+            name: "test".to_string(),
+            steps,
+        }
+    }
+
+    fn moved(from: &Path, to: &Path) -> Step {
+        fs::rename(from, to).unwrap();
+        Step::new(Change::Move {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        })
+    }
+
+    #[test]
+    fn check_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        let mut op = operation(vec![moved(&a, &b)]);
+        assert!(op.check(true).is_ok());
+        assert!(op.check(false).is_err(), "a is gone");
+    }
+
+    #[test]
+    fn check_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        let mut op = operation(vec![moved(&a, &b)]);
+        // Written beside it first, so the inode is not reused.
+        let c = dir.path().join("c");
+        fs::write(&c, "b").unwrap();
+        fs::rename(&c, &b).unwrap();
+        let err = op.check(true).unwrap_err().to_string();
+        assert!(err.contains("was replaced"), "{}", err);
+    }
+
+    #[test]
+    fn check_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        let mut op = operation(vec![moved(&a, &b)]);
+        fs::remove_file(&b).unwrap();
+        let err = op.check(true).unwrap_err().to_string();
+        assert!(err.contains("is gone"), "{}", err);
+    }
+
+    #[test]
+    fn check_taken() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        let mut op = operation(vec![moved(&a, &b)]);
+        fs::write(&a, "new").unwrap();
+        let err = op.check(true).unwrap_err().to_string();
+        assert!(err.contains("already exists"), "{}", err);
+    }
+
+    #[test]
+    fn check_non_empty_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let (d, f) = (dir.path().join("d"), dir.path().join("d/f"));
+        fs::create_dir(&d).unwrap();
+        let create_dir = || {
+            Step::new(Change::Create {
+                path: d.clone(),
+                is_dir: true,
+            })
+        };
+        fs::write(&f, "").unwrap();
+        let mut op = operation(vec![create_dir()]);
+        let err = op.check(true).unwrap_err().to_string();
+        assert!(err.contains("is not empty"), "{}", err);
+
+        // The file is removed first when it was created by the same operation.
+        let create_file = Step::new(Change::Create {
+            path: f.clone(),
+            is_dir: false,
+        });
+        let mut op = operation(vec![create_dir(), create_file]);
+        assert!(op.check(true).is_ok());
+    }
+
+    #[test]
+    fn check_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        fs::hard_link(&a, &b).unwrap();
+        let mut op = operation(vec![Step::new(Change::Link {
+            target: a.clone(),
+            path: b.clone(),
+            hard: true,
+        })]);
+        assert!(op.check(true).is_ok());
+        fs::remove_file(&b).unwrap();
+        op.steps[0].stamp = None;
+        assert!(op.check(false).is_ok());
+        fs::remove_file(&a).unwrap();
+        assert!(op.check(false).is_err(), "a hard link needs its target");
+    }
+
+    #[test]
+    fn check_copy_then_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        fs::write(&a, "a").unwrap();
+        fs::copy(&a, &b).unwrap();
+        let copied = Step::new(Change::Copy {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        let mut op = operation(vec![copied, moved(&b, &c)]);
+        assert!(op.check(true).is_ok());
+
+        // Once undone, redoing copies `a` to `b` again before moving it.
+        fs::rename(&c, &b).unwrap();
+        fs::remove_file(&b).unwrap();
+        for step in &mut op.steps {
+            step.stamp = None;
+        }
+        assert!(op.check(false).is_ok());
+        fs::remove_file(&a).unwrap();
+        assert!(op.check(false).is_err(), "a is gone");
+    }
+}
@@ -1,16 +1,34 @@
+use std::io::{self, Write};
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use structopt::StructOpt;
+use unicode_width::UnicodeWidthStr;
 
 use app::App;
+use bookmarks::Bookmarks;
 
 mod app;
+mod archive;
+mod bookmarks;
+mod bulk_rename;
+mod config;
+mod dir_sizes;
+mod disk_usage;
 mod file_manager;
+mod file_ops;
+mod git;
+mod icons;
+mod journal;
+mod permissions;
+mod popup;
+mod search;
 mod shell;
 mod status_bar;
 mod system_monitor;
 mod task_manager;
+mod theme;
+mod trash;
 
 /// A tiny file manager focused on shell integration
 #[derive(Debug, StructOpt)]
@@ -25,10 +43,40 @@ enum Command {
     ZshInit,
     GetCmd,
 
-    Cd { dir: PathBuf },
-    SendPid { pid: i32 },
-    SendTask { command: String, rendered: String },
+    Cd {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+    SendPid {
+        pid: i32,
+    },
+    SendTask {
+        command: String,
+        rendered: String,
+    },
+    Prompt,
+    Edited {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
     Exit,
+
+    /// Manage directory bookmarks
+    Bookmark(BookmarkCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum BookmarkCommand {
+    /// Bookmark a directory, the current one by default
+    Add {
+        name: String,
+        #[structopt(parse(from_os_str))]
+        dir: Option<PathBuf>,
+    },
+    /// Remove a bookmark
+    Rm { name: String },
+    /// List all bookmarks
+    Ls,
 }
 
 fn main() -> Result<()> {
@@ -38,14 +86,41 @@ fn main() -> Result<()> {
         Some(command) => match command {
             Command::FishInit => println!("{}", shell::FISH_INIT),
             Command::ZshInit => println!("{}", shell::ZSH_INIT),
-            Command::GetCmd => println!("{}", shell::receive_command()?),
+            Command::GetCmd => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&shell::receive_command()?)?;
+                stdout.write_all(b"\n")?;
+            }
 
             Command::SendPid { pid } => shell::send_event(shell::Event::Pid(pid))?,
             Command::SendTask { command, rendered } => {
                 shell::send_event(shell::Event::Task { command, rendered })?
             }
             Command::Cd { dir } => shell::send_event(shell::Event::ChangeDirectory(dir))?,
+            Command::Prompt => shell::send_event(shell::Event::Prompt)?,
+            Command::Edited { file } => shell::send_event(shell::Event::Edited(file))?,
             Command::Exit => shell::send_event(shell::Event::Exit)?,
+            Command::Bookmark(command) => {
+                let mut bookmarks = Bookmarks::load()?;
+                match command {
+                    BookmarkCommand::Add { name, dir } => {
+                        let dir = dir.unwrap_or_else(|| PathBuf::from("."));
+                        let dir = dir
+                            .canonicalize()
+                            .with_context(|| format!("Can't find {}", dir.display()))?;
+                        ensure!(dir.is_dir(), "{} is not a directory", dir.display());
+                        bookmarks.add(name, dir)?;
+                    }
+                    BookmarkCommand::Rm { name } => bookmarks.remove(&name)?,
+                    BookmarkCommand::Ls => {
+                        let width = bookmarks.0.keys().map(|n| n.width()).max().unwrap_or(0);
+                        for (name, dir) in &bookmarks.0 {
+                            let padding = " ".repeat(width - name.width());
+                            println!("{}{}  {}", name, padding, dir.display());
+                        }
+                    }
+                }
+            }
         },
     }
     Ok(())
@@ -0,0 +1,319 @@
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::unistd::{self, FchownatFlags, Gid, Group, Uid, User};
+use strmode::strmode;
+use termion::event::Key;
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Clear, Paragraph, Text};
+use tui::Frame;
+
+use crate::popup;
+use crate::status_bar::StatusBar;
+use crate::task_manager::{Progress, TaskManager};
+
+const CLASSES: [&str; 3] = ["user", "group", "other"];
+const PERMISSIONS: [&str; 3] = ["read", "write", "execute"];
+/// The special bits, shown beside the class they apply to.
+const SPECIAL: [(u32, &str); 3] = [(0o4000, "setuid"), (0o2000, "setgid"), (0o1000, "sticky")];
+
+/// The bit at a cell of the grid, the last column holding the special bits.
+fn bit(row: usize, col: usize) -> u32 {
+    match col {
+        0..=2 => 0o400 >> (row * 3 + col),
+        _ => SPECIAL[row].0,
+    }
+}
+
+fn user_name(uid: Uid) -> String {
+    match User::from_uid(uid) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+fn group_name(gid: Gid) -> String {
+    match Group::from_gid(gid) {
+        Ok(Some(group)) => group.name,
+        _ => gid.to_string(),
+    }
+}
+
+/// The changes to make to each file.
+#[derive(Clone, Copy)]
+struct Changes {
+    mode: u32,
+    /// Bits that were toggled, set to their value in `mode` on every file.
+    touched: u32,
+    owner: Option<Uid>,
+    group: Option<Gid>,
+    recursive: bool,
+}
+
+impl Changes {
+    /// Change `path`, following it if it's a symlink given by the user.
+    fn apply(&self, path: &Path, follow: bool, progress: &Progress, count: &mut u64) -> Result<()> {
+        progress.check()?;
+        *count += 1;
+        if count.is_multiple_of(100) {
+            progress.update(format!("{} files", count));
+        }
+
+        let metadata = if follow {
+            fs::metadata(path)?
+        } else {
+            fs::symlink_metadata(path)?
+        };
+        if self.owner.is_some() || self.group.is_some() {
+            let flag = if follow {
+                FchownatFlags::FollowSymlink
+            } else {
+                FchownatFlags::NoFollowSymlink
+            };
+            unistd::fchownat(None, path, self.owner, self.group, flag)
+                .with_context(|| format!("Failed to change the owner of {}", path.display()))?;
+        }
+        // Symlinks have no permissions of their own. Changing the owner may clear
+        // setuid and setgid, so the mode is set after it.
+        if !metadata.file_type().is_symlink() && self.touched != 0 {
+            let mode = (metadata.mode() & !self.touched | self.mode & self.touched) & 0o7777;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to change the mode of {}", path.display()))?;
+        }
+        if self.recursive && metadata.is_dir() {
+            for entry in fs::read_dir(path)? {
+                self.apply(&entry?.path(), false, progress, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Edits the permissions, owner and group of some files, drawn over the file list.
+///
+/// The mode shown is the first file's. Only the toggled bits change on the others.
+pub struct PermissionEditor {
+    files: Vec<PathBuf>,
+    file_type: u32,
+    changes: Changes,
+    owner: String,
+    group: String,
+    /// Row and column in the grid of bits.
+    cursor: (usize, usize),
+}
+
+impl PermissionEditor {
+    pub fn new(files: Vec<PathBuf>) -> Result<PermissionEditor> {
+        let first = files.first().context("No files selected")?;
+        let metadata = fs::metadata(first)?;
+        Ok(PermissionEditor {
+            file_type: metadata.mode() & !0o7777,
+            changes: Changes {
+                mode: metadata.mode() & 0o7777,
+                touched: 0,
+                owner: None,
+                group: None,
+                recursive: false,
+            },
+            owner: user_name(Uid::from_raw(metadata.uid())),
+            group: group_name(Gid::from_raw(metadata.gid())),
+            cursor: (0, 0),
+            files,
+        })
+    }
+
+    fn toggle(&mut self, bit: u32) {
+        self.changes.mode ^= bit;
+        self.changes.touched |= bit;
+    }
+
+    /// Change the owner to a user name or ID. Only root may give files away.
+    pub fn set_owner(&mut self, name: &str) -> Result<()> {
+        let user = match name.parse() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+            Err(_) => User::from_name(name)?,
+        };
+        let user = user.with_context(|| format!("No user named {}", name))?;
+        self.changes.owner = Some(user.uid);
+        self.owner = user.name;
+        Ok(())
+    }
+
+    /// Change the group to a group name or ID. Users other than root
+    /// can only pick the groups they are in.
+    pub fn set_group(&mut self, name: &str) -> Result<()> {
+        let group = match name.parse() {
+            Ok(gid) => Group::from_gid(Gid::from_raw(gid))?,
+            Err(_) => Group::from_name(name)?,
+        };
+        let group = group.with_context(|| format!("No group named {}", name))?;
+        if !Uid::effective().is_root()
+            && group.gid != Gid::effective()
+            && !unistd::getgroups()?.contains(&group.gid)
+        {
+            bail!("You are not in group {}", group.name);
+        }
+        self.changes.group = Some(group.gid);
+        self.group = group.name;
+        Ok(())
+    }
+
+    /// Returns `true` when the editor should be closed.
+    pub fn on_key(
+        &mut self,
+        key: Key,
+        status_bar: &mut StatusBar,
+        task_manager: &mut TaskManager,
+    ) -> Result<bool> {
+        let (row, col) = self.cursor;
+        match key {
+            Key::Esc => return Ok(true),
+            Key::Char('h') | Key::Left if col > 0 => self.cursor.1 -= 1,
+            Key::Char('l') | Key::Right if col < 3 => self.cursor.1 += 1,
+            Key::Char('k') | Key::Up if row > 0 => self.cursor.0 -= 1,
+            Key::Char('j') | Key::Down if row < 2 => self.cursor.0 += 1,
+            Key::Char(' ') => self.toggle(bit(row, col)),
+            Key::Char('r') => self.toggle(bit(row, 0)),
+            Key::Char('w') => self.toggle(bit(row, 1)),
+            Key::Char('x') => self.toggle(bit(row, 2)),
+            Key::Char('s') => self.toggle(bit(row, 3)),
+            Key::Char('R') => self.changes.recursive = !self.changes.recursive,
+            Key::Char('o') => {
+                if !Uid::effective().is_root() {
+                    bail!("Only root can change the owner");
+                }
+                status_bar.edit(
+                    "Owner: ",
+                    self.owner.clone(),
+                    |_, _, _, _| Ok(()),
+                    |name, this, _, _| match &mut this.permissions {
+                        Some(editor) => editor.set_owner(name),
+                        None => Ok(()),
+                    },
+                );
+            }
+            Key::Char('g') => {
+                status_bar.edit(
+                    "Group: ",
+                    self.group.clone(),
+                    |_, _, _, _| Ok(()),
+                    |name, this, _, _| match &mut this.permissions {
+                        Some(editor) => editor.set_group(name),
+                        None => Ok(()),
+                    },
+                );
+            }
+            Key::Char('\n') => {
+                self.spawn(task_manager);
+                return Ok(true);
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn spawn(&self, task_manager: &mut TaskManager) {
+        let changes = self.changes;
+        if changes.touched == 0 && changes.owner.is_none() && changes.group.is_none() {
+            return;
+        }
+        let files = self.files.clone();
+        let name = match &files[..] {
+            [file] => format!(
+                "Change permissions of {}",
+                file.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            _ => format!("Change permissions of {} files", files.len()),
+        };
+        task_manager.spawn(name, move |progress| {
+            let mut count = 0;
+            for file in &files {
+                changes.apply(file, true, progress, &mut count)?;
+            }
+            Ok(())
+        });
+    }
+
+    pub fn draw(&self, frame: &mut Frame<impl Backend>, area: Rect) {
+        let title = match &self.files[..] {
+            [file] => format!(" Permissions of {} ", file.display()),
+            _ => format!(" Permissions of {} files ", self.files.len()),
+        };
+        let block = popup::block(&title);
+
+        let header = Style::default().fg(Color::LightYellow);
+        let cursor = Style::default().fg(Color::Black).bg(Color::Blue);
+        let changed = Style::default().fg(Color::LightCyan);
+        let mut texts = vec![Text::raw(" ".repeat(8))];
+        for permission in &PERMISSIONS {
+            texts.push(Text::styled(format!("{:10}", permission), header));
+        }
+        texts.push(Text::raw("\n"));
+        for (row, class) in CLASSES.iter().enumerate() {
+            texts.push(Text::styled(format!("{:8}", class), header));
+            for col in 0..4 {
+                let bit = bit(row, col);
+                let mark = if self.changes.mode & bit != 0 {
+                    'x'
+                } else {
+                    ' '
+                };
+                let style = if self.cursor == (row, col) {
+                    cursor
+                } else if self.changes.touched & bit != 0 {
+                    changed
+                } else {
+                    Style::default()
+                };
+                if col == 3 {
+                    texts.push(Text::styled(
+                        format!("[{}] {}", mark, SPECIAL[row].1),
+                        style,
+                    ));
+                } else {
+                    texts.push(Text::styled(format!("[{}]", mark), style));
+                    texts.push(Text::raw(" ".repeat(7)));
+                }
+            }
+            texts.push(Text::raw("\n"));
+        }
+
+        let owner_style = |is_changed| {
+            if is_changed {
+                changed
+            } else {
+                Style::default()
+            }
+        };
+        texts.push(Text::raw("\n"));
+        texts.push(Text::styled(
+            strmode(self.file_type | self.changes.mode),
+            Style::default().fg(Color::LightGreen),
+        ));
+        texts.push(Text::raw(format!(" {:04o} ", self.changes.mode)));
+        texts.push(Text::styled(
+            self.owner.clone(),
+            owner_style(self.changes.owner.is_some()),
+        ));
+        texts.push(Text::raw(":"));
+        texts.push(Text::styled(
+            self.group.clone(),
+            owner_style(self.changes.group.is_some()),
+        ));
+        texts.push(Text::raw(format!(
+            "\nRecursive: {}\n\n",
+            if self.changes.recursive { "yes" } else { "no" }
+        )));
+        texts.push(Text::styled(
+            "Space/r/w/x/s: toggle  R: recursive  o: owner  g: group\nEnter: apply  Esc: cancel",
+            Style::default().fg(Color::DarkGray),
+        ));
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(texts.iter()).block(block), area);
+    }
+}
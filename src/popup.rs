@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Clear, List, ListState, Text};
+use tui::Frame;
+
+use crate::app::ListExt;
+
+/// The frame of a view drawn over the file list.
+pub fn block(title: &str) -> Block<'_> {
+    Block::default()
+        .title(title)
+        .title_style(Style::default().modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::LightYellow))
+}
+
+/// What a popup lists, which decides how its keys are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupKind {
+    /// Recently visited directories.
+    History,
+
+    /// Named directories.
+    Bookmarks,
+
+    /// Marked files across all directories.
+    Marked,
+
+    /// Files found by a recursive search.
+    Search,
+
+    /// Lines found by a content search.
+    Grep,
+
+    /// Pending renames of a bulk rename, waiting to be confirmed.
+    Rename,
+
+    /// Trashed files, which can be restored.
+    Trash,
+}
+
+#[derive(Debug, Clone)]
+pub struct PopupItem {
+    pub label: String,
+    pub path: PathBuf,
+    /// Line to open the file at.
+    pub line: Option<usize>,
+}
+
+/// A list drawn over the file list.
+pub struct Popup {
+    pub kind: PopupKind,
+    pub title: String,
+    all_items: Vec<PopupItem>,
+    pub items: Vec<PopupItem>, // filtered
+    pub filter: String,
+    list_state: ListState,
+}
+
+impl Popup {
+    pub fn new(kind: PopupKind, title: impl Into<String>, items: Vec<PopupItem>) -> Popup {
+        let mut popup = Popup {
+            kind,
+            title: title.into(),
+            all_items: items.clone(),
+            items,
+            filter: String::new(),
+            list_state: ListState::default(),
+        };
+        popup.select_first();
+        popup
+    }
+
+    pub fn apply_filter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.items = self
+            .all_items
+            .iter()
+            .filter(|item| item.label.to_lowercase().contains(&filter))
+            .cloned()
+            .collect();
+        self.select_first();
+    }
+
+    /// Add items, like results streamed from a search.
+    pub fn extend(&mut self, items: Vec<PopupItem>) {
+        if items.is_empty() {
+            return;
+        }
+        let filter = self.filter.to_lowercase();
+        self.items.extend(
+            items
+                .iter()
+                .filter(|item| item.label.to_lowercase().contains(&filter))
+                .cloned(),
+        );
+        self.all_items.extend(items);
+        if self.list_state.selected().is_none() {
+            self.select_first();
+        }
+    }
+
+    /// Number of items before filtering.
+    pub fn len(&self) -> usize {
+        self.all_items.len()
+    }
+
+    /// Remove the selected item, from the unfiltered list too.
+    pub fn remove_selected(&mut self) -> Option<PopupItem> {
+        let index = self.list_state.selected()?;
+        let item = self.items.remove(index);
+        if let Some(i) = self.all_items.iter().position(|i| i.path == item.path) {
+            self.all_items.remove(i);
+        }
+        match self.items.len() {
+            0 => self.select(None),
+            len if index >= len => self.select(Some(len - 1)),
+            _ => {}
+        }
+        Some(item)
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame<impl Backend>, area: Rect) {
+        let title = if self.filter.is_empty() {
+            format!(" {} ", self.title)
+        } else {
+            format!(" {} /{} ", self.title, self.filter)
+        };
+        let block = block(&title);
+        let items = self.items.iter().map(|item| Text::raw(&item.label));
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::Blue));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+}
+
+impl ListExt for Popup {
+    type Item = PopupItem;
+
+    fn get_index(&self) -> Option<usize> {
+        self.list_state.selected()
+    }
+
+    fn get_list(&self) -> &[Self::Item] {
+        &self.items
+    }
+
+    fn select(&mut self, index: Option<usize>) {
+        self.list_state.select(index)
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Result;
+use glob::Pattern;
+use ignore::{DirEntry, WalkBuilder};
+use regex::Regex;
+
+use crate::popup::{Popup, PopupItem};
+use crate::task_manager::{Progress, TaskManager, REFRESH_INTERVAL};
+
+#[derive(Default)]
+struct Found {
+    items: Vec<PopupItem>,
+    done: bool,
+}
+
+/// A search running as a task, streaming its results into a popup.
+pub struct Search {
+    title: String,
+    found: Arc<Mutex<Found>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Search {
+    /// Find files below `root` whose names contain `query`, or match it if it is a glob.
+    pub fn find_files(
+        root: PathBuf,
+        query: &str,
+        gitignore: bool,
+        hidden: bool,
+        task_manager: &mut TaskManager,
+    ) -> Result<Search> {
+        let matcher: Box<dyn Fn(&str) -> bool + Send> = if query.contains(&['*', '?', '['][..]) {
+            let pattern = Pattern::new(query)?;
+            Box::new(move |name| pattern.matches(name))
+        } else {
+            let query = query.to_lowercase();
+            Box::new(move |name| name.to_lowercase().contains(&query))
+        };
+
+        let title = format!("Find {}", query);
+        Ok(Search::spawn(
+            title,
+            task_manager,
+            move |progress, found| {
+                walk(&root, gitignore, hidden, progress, |entry| {
+                    if matcher(&entry.file_name().to_string_lossy()) {
+                        let path = entry.into_path();
+                        found.lock().unwrap().items.push(PopupItem {
+                            label: relative(&root, &path),
+                            path,
+                            line: None,
+                        });
+                    }
+                })
+            },
+        ))
+    }
+
+    /// Find lines matching the regex `query` in files below `root`,
+    /// listed like `grep -n -C 1`.
+    pub fn grep(
+        root: PathBuf,
+        query: &str,
+        hidden: bool,
+        task_manager: &mut TaskManager,
+    ) -> Result<Search> {
+        let regex = Regex::new(query)?;
+        let title = format!("Grep {}", query);
+        Ok(Search::spawn(
+            title,
+            task_manager,
+            move |progress, found| {
+                walk(&root, true, hidden, progress, |entry| {
+                    if entry.file_type().is_some_and(|t| t.is_file()) {
+                        grep_file(&root, entry.path(), &regex, found);
+                    }
+                })
+            },
+        ))
+    }
+
+    fn spawn(
+        title: String,
+        task_manager: &mut TaskManager,
+        work: impl FnOnce(&Progress, &Mutex<Found>) -> Result<()> + Send + 'static,
+    ) -> Search {
+        let found = Arc::new(Mutex::new(Found::default()));
+        let cancelled = task_manager.spawn(title.clone(), {
+            let found = found.clone();
+            move |progress| {
+                let res = work(progress, &found);
+                found.lock().unwrap().done = true;
+                res
+            }
+        });
+        Search {
+            title,
+            found,
+            cancelled,
+        }
+    }
+
+    /// Move the results found so far into `popup`.
+    pub fn poll(&self, popup: &mut Popup) {
+        let mut found = self.found.lock().unwrap();
+        popup.extend(mem::take(&mut found.items));
+        popup.title = if found.done {
+            format!("{} ({})", self.title, popup.len())
+        } else {
+            format!("{} (searching, {})", self.title, popup.len())
+        };
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Call `f` on every entry below `root`, reporting progress and stopping when cancelled.
+fn walk(
+    root: &Path,
+    gitignore: bool,
+    hidden: bool,
+    progress: &Progress,
+    mut f: impl FnMut(DirEntry),
+) -> Result<()> {
+    let walker = WalkBuilder::new(root)
+        .hidden(!hidden)
+        .parents(gitignore)
+        .ignore(gitignore)
+        .git_ignore(gitignore)
+        .git_global(gitignore)
+        .git_exclude(gitignore)
+        .build();
+    let mut last_refresh = Instant::now();
+    let mut count = 0;
+    for entry in walker.filter_map(|e| e.ok()).filter(|e| e.depth() > 0) {
+        progress.check()?;
+        f(entry);
+        count += 1;
+        if last_refresh.elapsed() >= REFRESH_INTERVAL {
+            progress.update(format!("{} files", count));
+            last_refresh = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+/// Add the matching lines of `path` and one line of context around them.
+/// Files that look binary are skipped.
+fn grep_file(root: &Path, path: &Path, regex: &Regex, found: &Mutex<Found>) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+    let name = relative(root, path);
+    let mut items = vec![];
+    let mut previous: Option<String> = None;
+    let mut last_listed = 0;
+    let mut context_after = false;
+    for (n, line) in BufReader::new(file).split(b'\n').enumerate() {
+        let n = n + 1;
+        let line = match line {
+            Ok(line) if !line.contains(&0) => String::from_utf8_lossy(&line).into_owned(),
+            _ => return,
+        };
+        let mut push = |n, sep, text: &str| {
+            items.push(PopupItem {
+                label: format!("{}{}{}{}{}", name, sep, n, sep, text.replace('\t', "    ")),
+                path: path.to_owned(),
+                line: Some(n),
+            })
+        };
+        if regex.is_match(&line) {
+            if let Some(previous) = previous.as_ref().filter(|_| last_listed < n - 1) {
+                push(n - 1, '-', previous);
+            }
+            push(n, ':', &line);
+            last_listed = n;
+            context_after = true;
+        } else if context_after {
+            push(n, '-', &line);
+            last_listed = n;
+            context_after = false;
+        }
+        previous = Some(line);
+    }
+    found.lock().unwrap().items.extend(items);
+}
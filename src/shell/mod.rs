@@ -1,14 +1,16 @@
+use std::ffi::{OsStr, OsString};
 use std::fs::{File, OpenOptions};
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::mem;
-use std::path::PathBuf;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 
 use anyhow::{ensure, Context, Result};
 use crossbeam_channel::Sender;
 use nix::sys::signal::{kill, Signal};
 use nix::sys::stat::Mode;
 use nix::unistd::{mkfifo, Pid};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// Send shell commands from scd to shell.
 pub const CMDS_TO_RUN: &str = "/tmp/scd-cmds-to-run";
@@ -26,7 +28,13 @@ pub enum Event {
     Pid(i32),
 
     /// The shell's current directory was changed.
-    ChangeDirectory(PathBuf),
+    ChangeDirectory(#[serde(with = "path_bytes")] PathBuf),
+
+    /// A command finished and the shell is showing a new prompt.
+    Prompt,
+
+    /// A file opened in `$EDITOR` was saved.
+    Edited(#[serde(with = "path_bytes")] PathBuf),
 
     /// Shell exited.
     Exit,
@@ -35,6 +43,20 @@ pub enum Event {
     Task { command: String, rendered: String },
 }
 
+/// Paths are sent as bytes since they may not be valid UTF-8.
+mod path_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        path.as_os_str().as_bytes().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        Ok(OsString::from_vec(bytes).into())
+    }
+}
+
 fn write(mut file: impl Write, buf: impl AsRef<[u8]>) -> Result<()> {
     let buf = buf.as_ref();
     file.write_all(&buf.len().to_ne_bytes())?;
@@ -42,44 +64,78 @@ fn write(mut file: impl Write, buf: impl AsRef<[u8]>) -> Result<()> {
     Ok(())
 }
 
-fn read(mut file: impl Read) -> Result<String> {
+fn read(mut file: impl Read) -> Result<Vec<u8>> {
     let mut len_bytes = [0; mem::size_of::<usize>()];
     file.read_exact(&mut len_bytes)?;
     let len = usize::from_ne_bytes(len_bytes);
     let mut buf = vec![0; len];
     file.read_exact(&mut buf)?;
-    let s = String::from_utf8(buf)?;
-    Ok(s)
+    Ok(buf)
 }
 
-/// Run a command in the shell.
-pub fn run(pid: Pid, cmd: &str, args: &[impl AsRef<str>], echo: bool) -> Result<()> {
-    ensure!(pid.as_raw() > 0, "shell not initialized");
-    let _ = mkfifo(CMDS_TO_RUN, Mode::S_IRWXU);
+/// Quote a word for both POSIX shells and fish.
+///
+/// `\\` and `\'` are escapes inside single quotes of fish, so they are put outside.
+fn quote(word: &[u8]) -> Vec<u8> {
+    let mut res = vec![b'\''];
+    for &b in word {
+        if b == b'\'' || b == b'\\' {
+            res.extend_from_slice(&[b'\'', b'\\', b, b'\'']);
+        } else {
+            res.push(b);
+        }
+    }
+    res.push(b'\'');
+    res
+}
+
+/// Put the quoted `args` in place of `{}` in `cmd`, or after it if there is none.
+fn substitute(cmd: &str, args: &[impl AsRef<OsStr>]) -> Vec<u8> {
     let args = args
         .iter()
-        .map(|a| format!("'{}'", a.as_ref()))
+        .map(|a| quote(a.as_ref().as_bytes()))
         .collect::<Vec<_>>()
-        .join(" ");
-    let cmd = if cmd.contains("{}") {
-        cmd.replace("{}", &args)
+        .join(&b' ');
+    if cmd.contains("{}") {
+        cmd.split("{}")
+            .map(str::as_bytes)
+            .collect::<Vec<_>>()
+            .join(&args[..])
     } else {
-        format!("{} {}", cmd, args)
-    };
-    let cmd = if echo {
-        format!("scd_run_with_echo \"{}\"", cmd)
+        [cmd.as_bytes(), b" ", &args].concat()
+    }
+}
+
+/// Run a command in the shell.
+pub fn run(pid: Pid, cmd: &str, args: &[impl AsRef<OsStr>], echo: bool) -> Result<()> {
+    send(pid, substitute(cmd, args), echo)
+}
+
+/// Run a command in the shell once for each of `args`, one after another.
+pub fn run_each(pid: Pid, cmd: &str, args: &[impl AsRef<OsStr>], echo: bool) -> Result<()> {
+    let cmds: Vec<Vec<u8>> = args.iter().map(|a| substitute(cmd, &[a])).collect();
+    send(pid, cmds.join(&b"; "[..]), echo)
+}
+
+fn send(pid: Pid, cmd: Vec<u8>, echo: bool) -> Result<()> {
+    ensure!(pid.as_raw() > 0, "shell not initialized");
+    let _ = mkfifo(CMDS_TO_RUN, Mode::S_IRWXU);
+    let wrapper: &[u8] = if echo {
+        b"scd_run_with_echo "
     } else {
-        format!("scd_run_silently \"{}\"", cmd)
+        b"scd_run_silently "
     };
+    let cmd = [wrapper, &quote(&cmd)].concat();
 
     kill(pid, Signal::SIGUSR1).with_context(|| "Failed to notify the shell")?;
     let fifo = OpenOptions::new().write(true).open(CMDS_TO_RUN)?;
     write(fifo, cmd)
 }
+
 /// Receive a shell command to run.
 ///
 /// This function is called on the shell side.
-pub fn receive_command() -> Result<String> {
+pub fn receive_command() -> Result<Vec<u8>> {
     let _ = mkfifo(CMDS_TO_RUN, Mode::S_IRWXU);
     let file = File::open(CMDS_TO_RUN)?;
     read(file)
@@ -98,11 +154,85 @@ pub fn receive_events(tx: Sender<Event>) -> Result<()> {
     let _ = mkfifo(SHELL_EVENTS, Mode::S_IRWXU);
     loop {
         let fifo = File::open(SHELL_EVENTS)?;
-        let event = serde_yaml::from_str(&read(fifo)?)?;
+        let buf = match read(fifo) {
+            Ok(buf) => buf,
+            // Reopened before the last writer closed the fifo.
+            Err(e) if is_eof(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        let event = serde_yaml::from_slice(&buf)?;
         tx.send(event)?;
     }
 }
 
+fn is_eof(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
 pub fn deinit(pid: Pid) -> Result<()> {
     run(pid, "scd_deinit", &[""], false)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    /// Run `printf '%s\0'` with the quoted `words` in `sh` and return the words it got.
+    fn sh_words(words: &[&[u8]]) -> Vec<Vec<u8>> {
+        let args: Vec<&OsStr> = words.iter().map(|w| OsStr::from_bytes(w)).collect();
+        let cmd = substitute("printf '%s\\0'", &args);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(OsStr::from_bytes(&cmd))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output
+            .stdout
+            .split(|&b| b == 0)
+            .filter(|w| !w.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    #[test]
+    fn quote_special_bytes() {
+        assert_eq!(quote(b"a b"), b"'a b'");
+        assert_eq!(quote(b"it's"), b"'it'\\''s'");
+        assert_eq!(quote(b"a\\b"), b"'a'\\\\'b'");
+        assert_eq!(quote(b"a\nb"), b"'a\nb'");
+        assert_eq!(quote(b"\xff"), b"'\xff'");
+    }
+
+    #[test]
+    fn quote_round_trip() {
+        let words: &[&[u8]] = &[
+            b"a b",
+            b"it's",
+            b"a\\b",
+            b"\\'",
+            b"a\nb",
+            b"$HOME `x` *",
+            b"\xff\xfe",
+        ];
+        assert_eq!(sh_words(words), words);
+    }
+
+    #[test]
+    fn substitute_placeholder() {
+        assert_eq!(
+            substitute("git add --", &["a b", "c"]),
+            b"git add -- 'a b' 'c'"
+        );
+        assert_eq!(
+            substitute("cp {} {}.bak", &["a'b"]),
+            b"cp 'a'\\''b' 'a'\\''b'.bak"
+        );
+        assert_eq!(
+            substitute("ls", &[OsStr::from_bytes(b"\xff")]),
+            b"ls '\xff'".to_vec()
+        );
+    }
+}
@@ -14,6 +14,10 @@ function scd_cd --on-variable PWD
     scd cd $PWD
 end
 
+function scd_prompt --on-event fish_prompt
+    scd prompt
+end
+
 function scd_exit --on-event fish_exit
     scd exit
 end
@@ -45,5 +49,5 @@ scd_cd
 
 function scd_deinit
     bind --erase \r \cj
-    functions --erase scd_eval scd_run_silently scd_run_with_echo scd_cd scd_exit scd_send_task scd_enter_key scd_deinit
+    functions --erase scd_eval scd_run_silently scd_run_with_echo scd_cd scd_prompt scd_exit scd_send_task scd_enter_key scd_deinit
 end
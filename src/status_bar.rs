@@ -1,4 +1,4 @@
-use std::os::unix::fs::PermissionsExt;
+use std::mem;
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
@@ -15,6 +15,13 @@ use crate::app::ListExt;
 use crate::file_manager::FileManager;
 use crate::task_manager::TaskManager;
 
+// Callbacks get the status bar to ask a follow-up question.
+type Callback = Box<dyn Fn(&mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>>;
+type TextCallback =
+    Box<dyn Fn(&str, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>>;
+type CharCallback =
+    Box<dyn Fn(char, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>>;
+
 pub enum Mode {
     /// Show some properties of selected file/task.
     Normal,
@@ -23,9 +30,20 @@ pub enum Mode {
     Message { text: String, expire_at: Instant },
 
     /// Ask a yes/no question.
-    Ask {
+    Ask { prompt: String, on_yes: Callback },
+
+    /// Wait for a single key.
+    Choose {
         prompt: String,
-        on_yes: Box<dyn Fn(&mut FileManager, &mut TaskManager) -> Result<()>>,
+        on_choose: CharCallback,
+    },
+
+    /// Pick one of some choices by their keys. Upper case keys pick the
+    /// choice too, and other keys are ignored until `Esc`.
+    Select {
+        prompt: String,
+        choices: Vec<(char, &'static str)>,
+        on_select: CharCallback,
     },
 
     /// Edit some text.
@@ -33,8 +51,8 @@ pub enum Mode {
         prompt: String,
         text: String,
         cursor: usize,
-        on_change: Box<dyn Fn(&str, &mut FileManager, &mut TaskManager) -> Result<()>>,
-        on_enter: Box<dyn Fn(&str, &mut FileManager, &mut TaskManager) -> Result<()>>,
+        on_change: TextCallback,
+        on_enter: TextCallback,
     },
 }
 
@@ -57,7 +75,7 @@ impl StatusBar {
     pub fn ask(
         &mut self,
         prompt: impl Into<String>,
-        on_yes: impl Fn(&mut FileManager, &mut TaskManager) -> Result<()> + 'static,
+        on_yes: impl Fn(&mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()> + 'static,
     ) {
         self.mode = Mode::Ask {
             prompt: prompt.into(),
@@ -65,12 +83,40 @@ impl StatusBar {
         }
     }
 
+    pub fn choose(
+        &mut self,
+        prompt: impl Into<String>,
+        on_choose: impl Fn(char, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>
+            + 'static,
+    ) {
+        self.mode = Mode::Choose {
+            prompt: prompt.into(),
+            on_choose: Box::new(on_choose),
+        }
+    }
+
+    pub fn select(
+        &mut self,
+        prompt: impl Into<String>,
+        choices: &[(char, &'static str)],
+        on_select: impl Fn(char, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>
+            + 'static,
+    ) {
+        self.mode = Mode::Select {
+            prompt: prompt.into(),
+            choices: choices.to_vec(),
+            on_select: Box::new(on_select),
+        }
+    }
+
     pub fn edit(
         &mut self,
         prompt: impl Into<String>,
         text: impl Into<String>,
-        on_change: impl Fn(&str, &mut FileManager, &mut TaskManager) -> Result<()> + 'static,
-        on_enter: impl Fn(&str, &mut FileManager, &mut TaskManager) -> Result<()> + 'static,
+        on_change: impl Fn(&str, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>
+            + 'static,
+        on_enter: impl Fn(&str, &mut FileManager, &mut TaskManager, &mut StatusBar) -> Result<()>
+            + 'static,
     ) {
         let text = text.into();
         let cursor = text.len();
@@ -97,70 +143,104 @@ impl StatusBar {
         file_manager: &mut FileManager,
         task_manager: &mut TaskManager,
     ) -> Result<()> {
-        match &mut self.mode {
+        // Take the mode out so that callbacks can replace it.
+        match mem::replace(&mut self.mode, Mode::Normal) {
             Mode::Normal => panic!(),
-            Mode::Message { .. } => self.mode = Mode::Normal,
+            Mode::Message { .. } => {}
             Mode::Ask { on_yes, .. } => {
                 if key == Key::Char('y') {
-                    on_yes(file_manager, task_manager)?;
+                    on_yes(file_manager, task_manager, self)?;
                 }
-                self.mode = Mode::Normal;
             }
-            Mode::Edit {
-                text,
-                cursor,
-                on_change,
-                on_enter,
-                ..
+            Mode::Choose { on_choose, .. } => {
+                if let Key::Char(ch) = key {
+                    on_choose(ch, file_manager, task_manager, self)?;
+                }
+            }
+            Mode::Select {
+                prompt,
+                choices,
+                on_select,
             } => match key {
-                Key::Char('\n') => {
-                    on_enter(text, file_manager, task_manager)?;
-                    self.mode = Mode::Normal;
+                Key::Esc | Key::Ctrl('[') => {}
+                Key::Char(ch) if choices.iter().any(|&(c, _)| c == ch.to_ascii_lowercase()) => {
+                    on_select(ch, file_manager, task_manager, self)?;
                 }
-                Key::Esc | Key::Ctrl('[') => {
-                    on_change("", file_manager, task_manager)?;
-                    self.mode = Mode::Normal;
+                _ => {
+                    self.mode = Mode::Select {
+                        prompt,
+                        choices,
+                        on_select,
+                    }
                 }
+            },
+            Mode::Edit {
+                prompt,
+                mut text,
+                mut cursor,
+                on_change,
+                on_enter,
+            } => {
+                let changed = match key {
+                    Key::Char('\n') => return on_enter(&text, file_manager, task_manager, self),
+                    Key::Esc | Key::Ctrl('[') => {
+                        return on_change("", file_manager, task_manager, self)
+                    }
 
-                Key::Home | Key::Ctrl('a') => *cursor = 0,
-                Key::End | Key::Ctrl('e') => *cursor = text.len(),
-                Key::Left | Key::Ctrl('b') => {
-                    if *cursor > 0 {
-                        *cursor -= 1;
+                    Key::Home | Key::Ctrl('a') => {
+                        cursor = 0;
+                        false
                     }
-                }
-                Key::Right | Key::Ctrl('f') => {
-                    if *cursor < text.len() {
-                        *cursor += 1;
+                    Key::End | Key::Ctrl('e') => {
+                        cursor = text.len();
+                        false
+                    }
+                    // The cursor is a byte index, moved by whole characters.
+                    Key::Left | Key::Ctrl('b') if cursor > 0 => {
+                        cursor = previous_char(&text, cursor);
+                        false
+                    }
+                    Key::Right | Key::Ctrl('f') if cursor < text.len() => {
+                        cursor = next_char(&text, cursor);
+                        false
                     }
-                }
 
-                Key::Backspace | Key::Ctrl('h') => {
-                    if *cursor > 0 {
-                        text.remove(*cursor - 1);
-                        *cursor -= 1;
-                        on_change(text, file_manager, task_manager)?;
+                    Key::Backspace | Key::Ctrl('h') if cursor > 0 => {
+                        cursor = previous_char(&text, cursor);
+                        text.remove(cursor);
+                        true
                     }
-                }
-                Key::Delete | Key::Ctrl('d') => {
-                    if *cursor < text.len() {
-                        text.remove(*cursor);
-                        on_change(text, file_manager, task_manager)?;
+                    Key::Delete | Key::Ctrl('d') if cursor < text.len() => {
+                        text.remove(cursor);
+                        true
+                    }
+                    Key::Ctrl('u') => {
+                        text.clear();
+                        cursor = 0;
+                        true
                     }
-                }
-                Key::Ctrl('u') => {
-                    text.clear();
-                    *cursor = 0;
-                    on_change(text, file_manager, task_manager)?;
-                }
 
-                Key::Char(ch) => {
-                    text.insert(*cursor, ch);
-                    *cursor += 1;
-                    on_change(text, file_manager, task_manager)?;
-                }
-                _ => {}
-            },
+                    Key::Char(ch) => {
+                        text.insert(cursor, ch);
+                        cursor += ch.len_utf8();
+                        true
+                    }
+                    _ => false,
+                };
+                let res = if changed {
+                    on_change(&text, file_manager, task_manager, self)
+                } else {
+                    Ok(())
+                };
+                self.mode = Mode::Edit {
+                    prompt,
+                    text,
+                    cursor,
+                    on_change,
+                    on_enter,
+                };
+                return res;
+            }
         }
         Ok(())
     }
@@ -175,8 +255,11 @@ impl StatusBar {
         match &self.mode {
             Mode::Normal => {
                 if let Some(file) = file_manager.selected() {
-                    let mode = strmode(file.metadata.permissions().mode());
-                    let size = format_size(file.metadata.len());
+                    let mode = strmode(file.metadata.mode());
+                    let size = file_manager
+                        .size_of(file)
+                        .map(format_size)
+                        .unwrap_or_else(|| "...".to_string());
                     let texts = [
                         Text::styled(mode, Style::default().fg(Color::LightGreen)),
                         Text::raw(" "),
@@ -189,6 +272,9 @@ impl StatusBar {
                 }
 
                 let mut text = String::new();
+                if file_manager.visual.is_some() {
+                    text.push_str("VISUAL ");
+                }
                 if !file_manager.files_marked.is_empty() {
                     text.push_str("M:");
                     text.push_str(&file_manager.files_marked.len().to_string());
@@ -211,10 +297,24 @@ impl StatusBar {
                 let texts = [Text::styled(text, prompt_style)];
                 frame.render_widget(Paragraph::new(texts.iter()), area);
             }
-            Mode::Ask { prompt, .. } => {
+            Mode::Ask { prompt, .. } | Mode::Choose { prompt, .. } => {
                 let texts = [Text::styled(prompt, prompt_style)];
                 frame.render_widget(Paragraph::new(texts.iter()), area);
             }
+            Mode::Select {
+                prompt, choices, ..
+            } => {
+                let mut texts = vec![Text::styled(prompt, prompt_style)];
+                for (key, label) in choices {
+                    texts.push(Text::raw(" "));
+                    texts.push(Text::styled(
+                        key.to_string(),
+                        Style::default().fg(Color::LightCyan),
+                    ));
+                    texts.push(Text::raw(*label));
+                }
+                frame.render_widget(Paragraph::new(texts.iter()), area);
+            }
             Mode::Edit { prompt, text, .. } => {
                 let texts = [
                     Text::styled(prompt, prompt_style),
@@ -226,7 +326,23 @@ impl StatusBar {
     }
 }
 
-fn format_size(size: u64) -> String {
+/// Byte index of the character before `index` in `text`.
+fn previous_char(text: &str, index: usize) -> usize {
+    text[..index]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+/// Byte index of the character after the one at `index` in `text`.
+fn next_char(text: &str, index: usize) -> usize {
+    text[index..]
+        .chars()
+        .next()
+        .map_or(index, |ch| index + ch.len_utf8())
+}
+
+pub fn format_size(size: u64) -> String {
     const UNITS: &[(u64, &str)] = &[
         (1024 * 1024 * 1024 * 1024, "T"),
         (1024 * 1024 * 1024, "G"),
@@ -3,9 +3,12 @@ use std::env;
 use std::io::Write;
 use std::io::{self, BufRead, BufReader};
 use std::process::{ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use crossbeam_channel::{self as channel, Receiver, Sender};
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
@@ -19,24 +22,67 @@ use unicode_width::UnicodeWidthStr;
 use crate::app::ListExt;
 use crate::status_bar::StatusBar;
 
+/// How often a running task reports its progress.
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `Progress` and `Done` come from native tasks, the others from processes.
 pub enum Event {
     Stdout { pid: Pid, line: String },
     Stderr { pid: Pid, line: String },
     Exit { pid: Pid, exit_status: ExitStatus },
+    Progress { id: u64, status: String },
+    Done { id: u64, error: Option<String> },
 }
 
 pub enum Status {
     Running(String),
     Stopped,
     Exited(ExitStatus),
+    Done { success: bool },
+}
+
+pub enum Runner {
+    /// A shell command run as a child process.
+    Process {
+        pid: Pid,
+        #[allow(dead_code)]
+        stdin: ChildStdin,
+    },
+
+    /// Work done on a thread of scd, like copying files.
+    Native { id: u64, cancelled: Arc<AtomicBool> },
 }
 
 pub struct Task {
-    pub pid: Pid,
+    pub runner: Runner,
     pub command: String,
     pub rendered: String,
     pub status: Status,
-    stdin: ChildStdin,
+}
+
+/// Handle given to native tasks to report progress and check for cancellation.
+pub struct Progress {
+    id: u64,
+    tx: Sender<Event>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn update(&self, status: impl Into<String>) {
+        let status = status.into();
+        let _ = self.tx.send(Event::Progress {
+            id: self.id,
+            status,
+        });
+    }
+
+    /// Fails if the task was cancelled, meant to be called between units of work.
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            bail!("Cancelled");
+        }
+        Ok(())
+    }
 }
 
 impl Task {
@@ -68,8 +114,7 @@ impl Task {
                 stdout
                     .split(b'\r')
                     .map(Result::unwrap)
-                    .map(String::from_utf8)
-                    .map(Result::unwrap)
+                    .map(|line| String::from_utf8_lossy(&line).into_owned())
                     .for_each(|line| tx.send(Event::Stdout { pid, line }).unwrap());
 
                 let exit_status = child.wait().unwrap();
@@ -82,26 +127,33 @@ impl Task {
                 stderr
                     .split(b'\r')
                     .map(Result::unwrap)
-                    .map(String::from_utf8)
-                    .map(Result::unwrap)
+                    .map(|line| String::from_utf8_lossy(&line).into_owned())
                     .for_each(|line| tx.send(Event::Stderr { pid, line }).unwrap());
             }
         });
 
         Ok(Self {
-            pid,
+            runner: Runner::Process { pid, stdin },
             command,
             rendered,
             status: Status::Running("\u{f110} ".to_string()),
-            stdin,
         })
     }
+
+    fn is_process(&self, pid: Pid) -> bool {
+        matches!(self.runner, Runner::Process { pid: p, .. } if p == pid)
+    }
+
+    fn is_native(&self, id: u64) -> bool {
+        matches!(self.runner, Runner::Native { id: i, .. } if i == id)
+    }
 }
 
 pub struct TaskManager {
     tx: Sender<Event>,
     pub tasks: Vec<Task>,
     list_state: TaskListState,
+    next_native_id: u64,
 }
 
 impl TaskManager {
@@ -111,6 +163,7 @@ impl TaskManager {
             tx,
             tasks: vec![],
             list_state: TaskListState::default(),
+            next_native_id: 0,
         };
         Ok((task_manager, rx))
     }
@@ -118,7 +171,7 @@ impl TaskManager {
     pub fn on_event(&mut self, event: Event) {
         match event {
             Event::Stdout { pid, line } | Event::Stderr { pid, line } => {
-                let mut task = self.tasks.iter_mut().find(|t| t.pid == pid).unwrap();
+                let task = self.tasks.iter_mut().find(|t| t.is_process(pid)).unwrap();
                 let name = task.command.split(' ').next().unwrap();
                 let status = PARSERS
                     .get(name)
@@ -127,23 +180,50 @@ impl TaskManager {
                 task.status = Status::Running(status);
             }
             Event::Exit { pid, exit_status } => {
-                let mut task = self.tasks.iter_mut().find(|t| t.pid == pid).unwrap();
+                let task = self.tasks.iter_mut().find(|t| t.is_process(pid)).unwrap();
                 task.status = Status::Exited(exit_status);
-                self.tasks.sort_by_key(|t| match t.status {
-                    Status::Running(_) => 3,
-                    Status::Stopped => 2,
-                    Status::Exited(exit_status) => {
-                        if !exit_status.success() {
-                            1
-                        } else {
-                            0
-                        }
+                self.sort();
+            }
+            Event::Progress { id, status } => {
+                if let Some(task) = self.tasks.iter_mut().find(|t| t.is_native(id)) {
+                    task.status = Status::Running(status);
+                }
+            }
+            Event::Done { id, error } => {
+                if let Some(task) = self.tasks.iter_mut().find(|t| t.is_native(id)) {
+                    task.status = Status::Done {
+                        success: error.is_none(),
+                    };
+                    if let Some(error) = error {
+                        task.rendered = format!("{}: {}", task.rendered, error);
                     }
-                });
+                }
+                self.sort();
             }
         }
     }
 
+    fn sort(&mut self) {
+        self.tasks.sort_by_key(|t| match t.status {
+            Status::Running(_) => 3,
+            Status::Stopped => 2,
+            Status::Exited(exit_status) => {
+                if !exit_status.success() {
+                    1
+                } else {
+                    0
+                }
+            }
+            Status::Done { success } => {
+                if !success {
+                    1
+                } else {
+                    0
+                }
+            }
+        });
+    }
+
     pub fn new_task(&mut self, command: String, rendered: String) -> Result<()> {
         let task = Task::new(command, rendered, self.tx.clone())?;
         self.tasks.push(task);
@@ -151,6 +231,39 @@ impl TaskManager {
         Ok(())
     }
 
+    /// Run `work` on a new thread, shown as a task named `name`.
+    ///
+    /// The task can be cancelled from the task list or by setting the returned flag.
+    pub fn spawn(
+        &mut self,
+        name: impl Into<String>,
+        work: impl FnOnce(&Progress) -> Result<()> + Send + 'static,
+    ) -> Arc<AtomicBool> {
+        let id = self.next_native_id;
+        self.next_native_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let progress = Progress {
+            id,
+            tx: self.tx.clone(),
+            cancelled: cancelled.clone(),
+        };
+        let handle = cancelled.clone();
+        thread::spawn(move || {
+            let error = work(&progress).err().map(|e| e.to_string());
+            let _ = progress.tx.send(Event::Done { id, error });
+        });
+
+        let name = name.into();
+        self.tasks.push(Task {
+            runner: Runner::Native { id, cancelled },
+            command: name.clone(),
+            rendered: name,
+            status: Status::Running("\u{f110} ".to_string()),
+        });
+        self.select_first();
+        handle
+    }
+
     pub fn on_key(&mut self, key: Key, status_bar: &mut StatusBar) -> Result<()> {
         match key {
             Key::Char('c') => {
@@ -158,29 +271,40 @@ impl TaskManager {
                     .retain(|t| matches!(t.status, Status::Running(_)));
                 self.select_first();
             }
-            Key::Char('t') => {
-                if let Some(task) = self.selected() {
-                    let pid = task.pid;
-                    status_bar.ask(
-                        format!("Terminate '{}' with SIGTERM?", task.command),
-                        move |_, _| Ok(kill(pid, Signal::SIGTERM)?),
-                    );
-                }
-            }
-            Key::Char('9') => {
+            Key::Char('t') | Key::Char('9') => {
                 if let Some(task) = self.selected() {
-                    let pid = task.pid;
-                    status_bar.ask(
-                        format!("Kill '{}' with SIGKILL?", task.command),
-                        move |_, _| Ok(kill(pid, Signal::SIGKILL)?),
-                    );
+                    match &task.runner {
+                        Runner::Process { pid, .. } => {
+                            let pid = *pid;
+                            let (signal, prompt) = if key == Key::Char('t') {
+                                (Signal::SIGTERM, "Terminate '{}' with SIGTERM?")
+                            } else {
+                                (Signal::SIGKILL, "Kill '{}' with SIGKILL?")
+                            };
+                            status_bar.ask(prompt.replace("{}", &task.command), move |_, _, _| {
+                                Ok(kill(pid, signal)?)
+                            });
+                        }
+                        Runner::Native { cancelled, .. } => {
+                            let cancelled = cancelled.clone();
+                            status_bar.ask(
+                                format!("Cancel '{}'?", task.command),
+                                move |_, _, _| {
+                                    cancelled.store(true, Ordering::Relaxed);
+                                    Ok(())
+                                },
+                            );
+                        }
+                    }
                 }
             }
             Key::Char('z') => {
                 if let Some(idx) = self.list_state.selected {
                     let task = &mut self.tasks[idx];
-                    kill(task.pid, Signal::SIGINT)?;
-                    task.status = Status::Stopped;
+                    if let Runner::Process { pid, .. } = task.runner {
+                        kill(pid, Signal::SIGINT)?;
+                        task.status = Status::Stopped;
+                    }
                 }
             }
             key => self.on_list_key(key)?,
@@ -208,7 +332,7 @@ impl TaskManager {
             .iter()
             .map(|t| match &t.status {
                 Status::Running(s) => s.width(),
-                Status::Stopped | Status::Exited(_) => 1,
+                Status::Stopped | Status::Exited(_) | Status::Done { .. } => 1,
             })
             .max()
             .unwrap()
@@ -256,6 +380,13 @@ impl TaskManager {
                             (color::LightRed.fg_str(), "✗ ")
                         }
                     }
+                    Status::Done { success } => {
+                        if *success {
+                            (color::LightCyan.fg_str(), "✓ ")
+                        } else {
+                            (color::LightRed.fg_str(), "✗ ")
+                        }
+                    }
                 };
                 (task.rendered.as_str(), status_color, status)
             })
@@ -277,20 +408,12 @@ impl TaskManager {
     }
 }
 
+#[derive(Default)]
 pub struct TaskListState {
     offset: usize,
     selected: Option<usize>,
 }
 
-impl Default for TaskListState {
-    fn default() -> Self {
-        Self {
-            offset: 0,
-            selected: None,
-        }
-    }
-}
-
 impl ListExt for TaskManager {
     type Item = Task;
 
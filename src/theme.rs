@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::env;
+
+use anyhow::Result;
+use tui::style::{Color, Modifier, Style};
+
+use crate::config;
+use crate::file_manager::{FileInfo, Symlink};
+
+/// Used when `LS_COLORS` is not set, same as the defaults of `dircolors`.
+const DEFAULT_LS_COLORS: &str = "di=01;34:ln=01;36:pi=40;33:so=01;35:do=01;35:bd=40;33;01:\
+cd=40;33;01:or=40;31;01:su=37;41:sg=30;43:tw=30;42:ow=34;42:st=37;44:ex=01;32";
+
+/// File colours from `LS_COLORS`, overridden by `~/.config/scd/theme.yml`.
+///
+/// The config file uses the same keys and SGR codes as `LS_COLORS`:
+///
+/// ```yaml
+/// di: "01;34"
+/// "*.rs, *.toml": "38;5;208"
+/// ```
+pub struct Theme {
+    /// Styles of file types, keyed by the two-letter codes like `di` and `ln`.
+    types: HashMap<String, Style>,
+
+    /// Styles of name suffixes such as `*.tar.gz`, stored in lowercase.
+    suffixes: Vec<(String, Style)>,
+
+    /// `ln=target` colours links like the files they point to.
+    link_as_target: bool,
+}
+
+impl Theme {
+    pub fn load() -> Result<Theme> {
+        let ls_colors = env::var("LS_COLORS").unwrap_or_else(|_| DEFAULT_LS_COLORS.to_string());
+        let mut theme = Theme {
+            types: HashMap::new(),
+            suffixes: vec![],
+            link_as_target: false,
+        };
+        for entry in ls_colors.split(':') {
+            let mut kv = entry.splitn(2, '=');
+            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                theme.insert(key, value);
+            }
+        }
+        let overrides: HashMap<String, String> = config::load("theme.yml")?;
+        for (key, value) in config::split_keys(overrides) {
+            theme.insert(&key, &value);
+        }
+        Ok(theme)
+    }
+
+    fn insert(&mut self, key: &str, value: &str) {
+        if key == "ln" {
+            self.link_as_target = value == "target";
+            if self.link_as_target {
+                return;
+            }
+        }
+        let style = parse_sgr(value);
+        match key.strip_prefix('*') {
+            Some(suffix) => {
+                let suffix = suffix.to_lowercase();
+                self.suffixes.retain(|(s, _)| s != &suffix);
+                self.suffixes.push((suffix, style));
+            }
+            None => {
+                self.types.insert(key.to_string(), style);
+            }
+        }
+    }
+
+    pub fn style(&self, file: &FileInfo) -> Style {
+        if let Some(symlink) = &file.symlink {
+            if symlink.broken {
+                return self
+                    .type_style("or")
+                    .or_else(|| self.type_style("ln"))
+                    .unwrap_or_default();
+            }
+            if !self.link_as_target {
+                return self.type_style("ln").unwrap_or_default();
+            }
+        }
+
+        let metadata = &file.metadata;
+        let mode = metadata.mode();
+        let sticky = mode & 0o1000 != 0;
+        let other_writable = mode & 0o002 != 0;
+        let key = if metadata.is_dir() {
+            match (sticky, other_writable) {
+                (true, true) => "tw",
+                (false, true) => "ow",
+                (true, false) => "st",
+                (false, false) => "di",
+            }
+        } else if metadata.is_fifo() {
+            "pi"
+        } else if metadata.is_socket() {
+            "so"
+        } else if metadata.is_block_device() {
+            "bd"
+        } else if metadata.is_char_device() {
+            "cd"
+        } else if mode & 0o4000 != 0 {
+            "su"
+        } else if mode & 0o2000 != 0 {
+            "sg"
+        } else if mode & 0o111 != 0 {
+            "ex"
+        } else {
+            let name = file.display_name().to_lowercase();
+            let by_suffix = self
+                .suffixes
+                .iter()
+                .filter(|(suffix, _)| name.ends_with(suffix.as_str()))
+                .max_by_key(|(suffix, _)| suffix.len());
+            if let Some((_, style)) = by_suffix {
+                return *style;
+            }
+            "fi"
+        };
+        let style = self.type_style(key);
+        if metadata.is_dir() {
+            style.or_else(|| self.type_style("di")).unwrap_or_default()
+        } else {
+            style.unwrap_or_default()
+        }
+    }
+
+    /// Style of the ` -> target` part of symlinks.
+    pub fn link_target_style(&self, symlink: &Symlink) -> Style {
+        if symlink.broken {
+            self.type_style("mi")
+                .or_else(|| self.type_style("or"))
+                .unwrap_or_default()
+        } else {
+            Style::default().fg(Color::DarkGray)
+        }
+    }
+
+    fn type_style(&self, key: &str) -> Option<Style> {
+        self.types.get(key).copied()
+    }
+}
+
+/// Parse the SGR parameters of an `LS_COLORS` entry like `01;38;5;208`.
+fn parse_sgr(codes: &str) -> Style {
+    let mut style = Style::default();
+    let mut codes = codes.split(';').map(|c| c.parse::<u8>().unwrap_or(0));
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = Style::default(),
+            1 => style.modifier |= Modifier::BOLD,
+            2 => style.modifier |= Modifier::DIM,
+            3 => style.modifier |= Modifier::ITALIC,
+            4 => style.modifier |= Modifier::UNDERLINED,
+            5 => style.modifier |= Modifier::SLOW_BLINK,
+            6 => style.modifier |= Modifier::RAPID_BLINK,
+            7 => style.modifier |= Modifier::REVERSED,
+            8 => style.modifier |= Modifier::HIDDEN,
+            9 => style.modifier |= Modifier::CROSSED_OUT,
+            30..=37 => style.fg = ansi_color(code - 30),
+            39 => style.fg = Color::Reset,
+            40..=47 => style.bg = ansi_color(code - 40),
+            49 => style.bg = Color::Reset,
+            90..=97 => style.fg = ansi_color(code - 90 + 8),
+            100..=107 => style.bg = ansi_color(code - 100 + 8),
+            38 | 48 => {
+                let color = match codes.next() {
+                    Some(5) => codes.next().map(Color::Indexed),
+                    Some(2) => match (codes.next(), codes.next(), codes.next()) {
+                        (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(color) = color {
+                    if code == 38 {
+                        style.fg = color;
+                    } else {
+                        style.bg = color;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    style
+}
+
+fn ansi_color(index: u8) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use nix::unistd::Uid;
+
+use crate::task_manager::TaskManager;
+
+/// A file in a trash directory, as described by its `.trashinfo` file.
+pub struct TrashItem {
+    /// The trashed file, in the `files` directory of the trash.
+    pub file: PathBuf,
+    pub original: PathBuf,
+    /// As written in the info file, `YYYY-MM-DDThh:mm:ss` in local time.
+    pub deleted: String,
+}
+
+/// `$XDG_DATA_HOME/Trash`, used for files on the same filesystem as it.
+fn home_trash() -> Result<PathBuf> {
+    let data = match env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+        Some(data) => PathBuf::from(data),
+        None => Path::new(&env::var("HOME")?).join(".local/share"),
+    };
+    Ok(data.join("Trash"))
+}
+
+/// Device of `path`, or of its closest existing ancestor.
+fn device(path: &Path) -> io::Result<u64> {
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+    Ok(fs::metadata(existing)?.dev())
+}
+
+/// The mount point containing `dir`.
+fn top_dir(dir: &Path) -> io::Result<PathBuf> {
+    let dev = fs::metadata(dir)?.dev();
+    let mut top = dir;
+    for parent in dir.ancestors().skip(1) {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        top = parent;
+    }
+    Ok(top.to_owned())
+}
+
+/// The trash directories a mount point may have: `.Trash/$uid` if the
+/// administrator created a sticky `.Trash`, and the user's own `.Trash-$uid`.
+fn top_trashes(top: &Path) -> Vec<PathBuf> {
+    let uid = Uid::current();
+    let mut trashes = vec![];
+    let shared = top.join(".Trash");
+    if let Ok(metadata) = fs::symlink_metadata(&shared) {
+        if metadata.is_dir() && metadata.mode() & 0o1000 != 0 {
+            trashes.push(shared.join(uid.to_string()));
+        }
+    }
+    trashes.push(top.join(format!(".Trash-{}", uid)));
+    trashes
+}
+
+/// The trash directory for `path`, which must be on the same filesystem
+/// so the file can be renamed into it.
+fn trash_dir_for(path: &Path) -> Result<PathBuf> {
+    let parent = path.parent().context("Can't trash the root directory")?;
+    let home = home_trash()?;
+    if fs::metadata(parent)?.dev() == device(&home)? {
+        return Ok(home);
+    }
+    let top = top_dir(parent)?;
+    for trash in top_trashes(&top) {
+        if DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&trash)
+            .is_ok()
+        {
+            return Ok(trash);
+        }
+    }
+    bail!("No trash directory on the filesystem of {}", path.display())
+}
+
+/// Escape a path like a URL, as `.trashinfo` files require.
+fn encode(path: &Path) -> String {
+    let mut res = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                res.push(byte as char)
+            }
+            _ => res.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    res
+}
+
+fn decode(s: &str) -> PathBuf {
+    let bytes = s.as_bytes();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                res.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                res.push(byte);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(res))
+}
+
+/// Move `path` to the trash, returning where it went.
+pub fn trash(path: &Path) -> Result<PathBuf> {
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir()?.join(path)
+    };
+    let name = path.file_name().context("Can't trash this file")?;
+    let trash = trash_dir_for(&path)?;
+    for dir in &["files", "info"] {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(trash.join(dir))?;
+    }
+
+    // Creating the info file reserves the name in the trash.
+    for n in 1.. {
+        let mut trashed_name = name.to_owned();
+        if n > 1 {
+            trashed_name.push(format!(".{}", n));
+        }
+        let mut info_name = trashed_name.clone();
+        info_name.push(".trashinfo");
+        let info = trash.join("info").join(info_name);
+        let file = trash.join("files").join(&trashed_name);
+        let mut info_file = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(_) if fs::symlink_metadata(&file).is_ok() => {
+                fs::remove_file(&info)?;
+                continue;
+            }
+            Ok(info_file) => info_file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let res = write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode(&path),
+            Local::now().format("%Y-%m-%dT%H:%M:%S")
+        )
+        .and_then(|_| fs::rename(&path, &file));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&info);
+            return Err(e.into());
+        }
+        return Ok(file);
+    }
+    unreachable!()
+}
+
+/// The `.trashinfo` file of a trashed file.
+fn info_path(file: &Path) -> PathBuf {
+    let trash = file.parent().and_then(Path::parent).unwrap_or(file);
+    let mut name = file.file_name().unwrap_or_default().to_owned();
+    name.push(".trashinfo");
+    trash.join("info").join(name)
+}
+
+fn read_info(info: &Path, file: PathBuf) -> Result<TrashItem> {
+    let content = fs::read_to_string(info)?;
+    let mut original = None;
+    let mut deleted = String::new();
+    for line in content.lines() {
+        if let Some(path) = line.strip_prefix("Path=") {
+            original = Some(decode(path));
+        } else if let Some(date) = line.strip_prefix("DeletionDate=") {
+            deleted = date.to_owned();
+        }
+    }
+    let mut original = original.context("No path in the trash info")?;
+    // Paths in trashes on other filesystems may be relative to their mount point.
+    if original.is_relative() {
+        let trash = file.parent().and_then(Path::parent).unwrap_or(&file);
+        let top = match trash.parent() {
+            Some(parent) if parent.file_name() == Some(OsStr::new(".Trash")) => parent.parent(),
+            parent => parent,
+        };
+        original = top.unwrap_or(trash).join(original);
+    }
+    Ok(TrashItem {
+        file,
+        original,
+        deleted,
+    })
+}
+
+/// The mount points listed in `/proc/self/mounts`.
+fn mount_points() -> Vec<PathBuf> {
+    let mounts = fs::read("/proc/self/mounts").unwrap_or_default();
+    mounts
+        .split(|&b| b == b'\n')
+        .filter_map(|line| line.split(|&b| b == b' ').nth(1))
+        // Spaces and such are escaped like `\040`.
+        .map(|point| {
+            let mut res = vec![];
+            let mut i = 0;
+            while i < point.len() {
+                let octal = point
+                    .get(i + 1..i + 4)
+                    .and_then(|o| u8::from_str_radix(&String::from_utf8_lossy(o), 8).ok());
+                match (point[i], octal) {
+                    (b'\\', Some(byte)) => {
+                        res.push(byte);
+                        i += 4;
+                    }
+                    (byte, _) => {
+                        res.push(byte);
+                        i += 1;
+                    }
+                }
+            }
+            PathBuf::from(OsString::from_vec(res))
+        })
+        .collect()
+}
+
+/// Everything in the home trash and the trashes of mounted filesystems, latest first.
+pub fn list() -> Result<Vec<TrashItem>> {
+    let mut trashes = vec![home_trash()?];
+    for point in mount_points() {
+        trashes.extend(top_trashes(&point));
+    }
+    // The same filesystem may be mounted more than once.
+    let mut seen = HashSet::new();
+    trashes.retain(|trash| seen.insert(trash.clone()));
+
+    let mut items = vec![];
+    for trash in trashes {
+        let entries = match fs::read_dir(trash.join("info")) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let info = entry.path();
+            if info.extension() != Some(OsStr::new("trashinfo")) {
+                continue;
+            }
+            let file = trash
+                .join("files")
+                .join(info.file_stem().unwrap_or_default());
+            if fs::symlink_metadata(&file).is_err() {
+                continue;
+            }
+            if let Ok(item) = read_info(&info, file) {
+                items.push(item);
+            }
+        }
+    }
+    items.sort_by(|a, b| b.deleted.cmp(&a.deleted));
+    Ok(items)
+}
+
+/// Move a trashed file back to where it was, returning that path.
+pub fn restore(file: &Path) -> Result<PathBuf> {
+    let info = info_path(file);
+    let item = read_info(&info, file.to_owned())?;
+    if fs::symlink_metadata(&item.original).is_ok() {
+        bail!("{} already exists", item.original.display());
+    }
+    if let Some(parent) = item.original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(file, &item.original)?;
+    fs::remove_file(info)?;
+    Ok(item.original)
+}
+
+/// Remove a trashed file for good.
+fn remove(file: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(file)?.is_dir() {
+        fs::remove_dir_all(file)?;
+    } else {
+        fs::remove_file(file)?;
+    }
+    fs::remove_file(info_path(file))
+}
+
+/// Remove trashed files for good, as a task.
+pub fn spawn_remove(task_manager: &mut TaskManager, files: Vec<PathBuf>) {
+    let name = match files.len() {
+        1 => "Remove 1 file from the trash".to_string(),
+        n => format!("Remove {} files from the trash", n),
+    };
+    task_manager.spawn(name, move |progress| {
+        for (i, file) in files.iter().enumerate() {
+            progress.check()?;
+            progress.update(format!("{}/{}", i, files.len()));
+            remove(file)?;
+        }
+        Ok(())
+    });
+}